@@ -0,0 +1,11 @@
+CREATE TABLE idempotency(
+    user_id uuid NOT NULL
+        REFERENCES users (user_id),
+    idempotency_key TEXT NOT NULL,
+    response_status_code SMALLINT NULL,
+    response_header_names TEXT[] NULL,
+    response_header_values BYTEA[] NULL,
+    response_body BYTEA NULL,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (user_id, idempotency_key)
+);
@@ -0,0 +1,15 @@
+BEGIN;
+    ALTER TABLE issue_delivery_queue ADD COLUMN status TEXT NULL;
+    ALTER TABLE issue_delivery_queue ADD COLUMN n_attempts SMALLINT NULL;
+    ALTER TABLE issue_delivery_queue ADD COLUMN execute_after timestamptz NULL;
+    ALTER TABLE issue_delivery_queue ADD COLUMN last_error TEXT NULL;
+    UPDATE issue_delivery_queue
+        SET status = 'pending', n_attempts = 0, execute_after = now()
+        WHERE status IS NULL;
+    ALTER TABLE issue_delivery_queue ALTER COLUMN status SET NOT NULL;
+    ALTER TABLE issue_delivery_queue ALTER COLUMN n_attempts SET NOT NULL;
+    ALTER TABLE issue_delivery_queue ALTER COLUMN execute_after SET NOT NULL;
+    CREATE INDEX issue_delivery_queue_pending_idx
+        ON issue_delivery_queue (execute_after)
+        WHERE status = 'pending';
+COMMIT;
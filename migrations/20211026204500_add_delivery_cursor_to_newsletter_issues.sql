@@ -0,0 +1 @@
+ALTER TABLE newsletter_issues ADD COLUMN delivery_cursor uuid NULL;
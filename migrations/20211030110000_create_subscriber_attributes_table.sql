@@ -0,0 +1,7 @@
+CREATE TABLE subscriber_attributes(
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id),
+    name TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (subscriber_id, name)
+);
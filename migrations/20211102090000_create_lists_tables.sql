@@ -0,0 +1,38 @@
+BEGIN;
+    CREATE TABLE lists(
+        list_id uuid NOT NULL,
+        PRIMARY KEY (list_id),
+        name TEXT NOT NULL UNIQUE,
+        created_at timestamptz NOT NULL
+    );
+    -- Everything that predates lists belongs to the original newsletter.
+    INSERT INTO lists (list_id, name, created_at)
+        VALUES ('5c1f5d1e-7a43-4c1b-9a8e-0f7d2b6c4e11', 'newsletter', now());
+
+    CREATE TABLE list_subscriptions(
+        list_id uuid NOT NULL
+            REFERENCES lists (list_id),
+        subscriber_id uuid NOT NULL
+            REFERENCES subscriptions (id),
+        status TEXT NOT NULL,
+        subscribed_at timestamptz NOT NULL,
+        PRIMARY KEY (list_id, subscriber_id)
+    );
+    INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+        SELECT '5c1f5d1e-7a43-4c1b-9a8e-0f7d2b6c4e11', id, status, subscribed_at
+        FROM subscriptions;
+
+    ALTER TABLE subscription_tokens ADD COLUMN list_id uuid NULL
+        REFERENCES lists (list_id);
+    UPDATE subscription_tokens
+        SET list_id = '5c1f5d1e-7a43-4c1b-9a8e-0f7d2b6c4e11'
+        WHERE list_id IS NULL;
+    ALTER TABLE subscription_tokens ALTER COLUMN list_id SET NOT NULL;
+
+    ALTER TABLE newsletter_issues ADD COLUMN list_id uuid NULL
+        REFERENCES lists (list_id);
+    UPDATE newsletter_issues
+        SET list_id = '5c1f5d1e-7a43-4c1b-9a8e-0f7d2b6c4e11'
+        WHERE list_id IS NULL;
+    ALTER TABLE newsletter_issues ALTER COLUMN list_id SET NOT NULL;
+COMMIT;
@@ -0,0 +1,9 @@
+BEGIN;
+    ALTER TABLE subscription_tokens ADD COLUMN created_at timestamptz NULL;
+    ALTER TABLE subscription_tokens ADD COLUMN expires_at timestamptz NULL;
+    UPDATE subscription_tokens
+        SET created_at = now(), expires_at = now() + interval '1 day'
+        WHERE created_at IS NULL;
+    ALTER TABLE subscription_tokens ALTER COLUMN created_at SET NOT NULL;
+    ALTER TABLE subscription_tokens ALTER COLUMN expires_at SET NOT NULL;
+COMMIT;
@@ -0,0 +1,10 @@
+-- Tokens are looked up by their keyed hash from now on. The HMAC secret only
+-- lives in the application configuration, so outstanding plaintext tokens are
+-- hashed (and their plaintext cleared) by the application when it starts.
+BEGIN;
+    ALTER TABLE subscription_tokens ADD COLUMN subscription_token_hash TEXT NULL UNIQUE;
+    ALTER TABLE subscription_tokens DROP CONSTRAINT subscription_tokens_pkey;
+    ALTER TABLE subscription_tokens ALTER COLUMN subscription_token DROP NOT NULL;
+    ALTER TABLE subscription_tokens ADD CONSTRAINT subscription_tokens_token_or_hash
+        CHECK (subscription_token IS NOT NULL OR subscription_token_hash IS NOT NULL);
+COMMIT;
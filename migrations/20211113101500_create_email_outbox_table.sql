@@ -0,0 +1,16 @@
+CREATE TABLE email_outbox(
+    email_id uuid NOT NULL,
+    PRIMARY KEY (email_id),
+    recipient TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    html_body TEXT NOT NULL,
+    text_body TEXT NOT NULL,
+    status TEXT NOT NULL,
+    n_attempts SMALLINT NOT NULL,
+    execute_after timestamptz NOT NULL,
+    last_error TEXT NULL,
+    created_at timestamptz NOT NULL
+);
+CREATE INDEX email_outbox_pending_idx
+    ON email_outbox (execute_after)
+    WHERE status = 'pending';
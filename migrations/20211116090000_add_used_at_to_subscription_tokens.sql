@@ -0,0 +1,3 @@
+-- Used tokens are kept until they expire so that a second click on a
+-- confirmation link can be told apart from an unknown token.
+ALTER TABLE subscription_tokens ADD COLUMN used_at TIMESTAMPTZ NULL;
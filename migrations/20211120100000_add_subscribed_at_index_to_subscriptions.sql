@@ -0,0 +1 @@
+CREATE INDEX subscriptions_subscribed_at_idx ON subscriptions (subscribed_at, id);
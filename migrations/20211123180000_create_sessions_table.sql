@@ -0,0 +1,7 @@
+CREATE TABLE sessions(
+    session_id uuid NOT NULL,
+    PRIMARY KEY (session_id),
+    user_id uuid NOT NULL REFERENCES users (user_id),
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL
+);
@@ -0,0 +1,8 @@
+CREATE TABLE failed_logins(
+    kind TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    PRIMARY KEY (kind, subject),
+    failures INT NOT NULL,
+    last_failure_at timestamptz NOT NULL,
+    blocked_until timestamptz NULL
+);
@@ -0,0 +1,7 @@
+CREATE TABLE user_roles(
+    user_id uuid NOT NULL REFERENCES users (user_id),
+    role TEXT NOT NULL CHECK (role IN ('admin', 'editor', 'viewer')),
+    PRIMARY KEY (user_id, role)
+);
+-- Users could do everything until now, they keep doing so.
+INSERT INTO user_roles (user_id, role) SELECT user_id, 'admin' FROM users;
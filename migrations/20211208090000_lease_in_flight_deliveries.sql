@@ -0,0 +1,8 @@
+-- Deliveries are claimed as 'in_flight' with a lease in execute_after, and
+-- claimed again once it expires, so the index covers both statuses.
+BEGIN;
+    DROP INDEX issue_delivery_queue_pending_idx;
+    CREATE INDEX issue_delivery_queue_claimable_idx
+        ON issue_delivery_queue (execute_after)
+        WHERE status IN ('pending', 'in_flight');
+COMMIT;
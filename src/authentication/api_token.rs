@@ -0,0 +1,151 @@
+use std::fmt::Display;
+
+use anyhow::Context;
+use chrono::Utc;
+use hmac::{Hmac, Mac, NewMac};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sha2::Sha256;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::AuthError;
+use crate::startup::HmacSecret;
+
+const TOKEN_PREFIX: &str = "z2p_";
+
+/// An operation an API token can be allowed to perform.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum Scope {
+    #[serde(rename = "newsletter:publish")]
+    NewsletterPublish,
+    #[serde(rename = "subscribers:read")]
+    SubscribersRead,
+    #[serde(rename = "subscribers:write")]
+    SubscribersWrite,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::NewsletterPublish => "newsletter:publish",
+            Scope::SubscribersRead => "subscribers:read",
+            Scope::SubscribersWrite => "subscribers:write",
+        }
+    }
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A personal access token, handed out once when created. Only its hash is stored.
+pub struct ApiToken(String);
+
+impl ApiToken {
+    pub fn generate() -> Self {
+        let mut rng = thread_rng();
+        let random: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+            .map(char::from)
+            .take(40)
+            .collect();
+        Self(format!("{}{}", TOKEN_PREFIX, random))
+    }
+
+    pub fn hash(&self, secret: &HmacSecret) -> String {
+        hash_api_token(&self.0, secret)
+    }
+}
+
+impl AsRef<str> for ApiToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+fn hash_api_token(token: &str, secret: &HmacSecret) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.0.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(b"api_token:");
+    mac.update(token.as_bytes());
+    base64::encode(mac.finalize().into_bytes())
+}
+
+#[tracing::instrument(name = "validating an API token", skip(token, secret, pool))]
+pub(super) async fn authenticate_api_token(
+    token: &str,
+    scope: Scope,
+    secret: &HmacSecret,
+    pool: &PgPool,
+) -> Result<Uuid, AuthError> {
+    let now = Utc::now();
+    let stored = sqlx::query!(
+        r#"
+        SELECT token_id, user_id, scopes
+        FROM api_tokens
+        WHERE token_hash = $1 AND expires_at > $2
+        "#,
+        hash_api_token(token, secret),
+        now
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to look up API token")?
+    .ok_or_else(|| {
+        AuthError::InvalidCredentials(anyhow::anyhow!("unknown or expired API token"))
+    })?;
+
+    if !stored
+        .scopes
+        .iter()
+        .any(|granted| granted == scope.as_str())
+    {
+        return Err(AuthError::MissingScope(scope));
+    }
+
+    sqlx::query!(
+        "UPDATE api_tokens SET last_used_at = $2 WHERE token_id = $1",
+        stored.token_id,
+        now
+    )
+    .execute(pool)
+    .await
+    .context("failed to record API token use")?;
+
+    Ok(stored.user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash_api_token, ApiToken, Scope};
+    use crate::startup::HmacSecret;
+
+    #[test]
+    fn tokens_are_prefixed_and_hashed_with_the_secret() {
+        let secret = HmacSecret("a-secret".to_string());
+        let token = ApiToken::generate();
+
+        assert!(token.as_ref().starts_with("z2p_"));
+        assert_eq!(token.hash(&secret), hash_api_token(token.as_ref(), &secret));
+        assert_ne!(
+            token.hash(&secret),
+            token.hash(&HmacSecret("another-secret".to_string()))
+        );
+    }
+
+    #[test]
+    fn scopes_use_their_names_in_json() {
+        let scopes: Vec<Scope> =
+            serde_json::from_str(r#"["newsletter:publish", "subscribers:read"]"#).unwrap();
+
+        assert_eq!(
+            scopes,
+            vec![Scope::NewsletterPublish, Scope::SubscribersRead]
+        );
+        assert_eq!(
+            serde_json::to_string(&Scope::SubscribersWrite).unwrap(),
+            r#""subscribers:write""#
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use std::time::Duration;
+
+use actix_http::header::HeaderMap;
+use actix_web::{web, HttpRequest};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+mod api_token;
+mod password;
+mod throttle;
+
+use api_token::authenticate_api_token;
+pub use api_token::{ApiToken, Scope};
+use password::validate_credentials;
+pub use password::{change_password, Credentials};
+pub use throttle::LoginThrottle;
+
+use crate::{common::error_chain_fmt, session::SessionUser, startup::HmacSecret};
+
+#[derive(thiserror::Error)]
+pub enum AuthError {
+    #[error("Invalid credentials")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error("Too many failed login attempts")]
+    TooManyAttempts(Duration),
+    #[error("The API token was not granted the {0} scope")]
+    MissingScope(Scope),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Identifies the user behind a request on behalf of an operation covered by
+/// `scope`. On top of what `authenticate_user` accepts, this lets through
+/// bearer API tokens that were granted the scope.
+pub async fn authenticate(
+    request: &HttpRequest,
+    pool: &PgPool,
+    scope: Scope,
+) -> Result<Uuid, AuthError> {
+    match bearer_token(request.headers()) {
+        Some(token) => {
+            let secret = request
+                .app_data::<web::Data<HmacSecret>>()
+                .context("the HMAC secret is not configured")?;
+            let user_id = authenticate_api_token(token, scope, secret, pool).await?;
+            tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+            Ok(user_id)
+        }
+        None => authenticate_user(request, pool).await,
+    }
+}
+
+/// Identifies the user behind a request, either from the login session put in
+/// place by `RequireSession` or from Basic credentials. API tokens are not
+/// accepted: this guards what only a person should do, such as managing tokens.
+pub async fn authenticate_user(request: &HttpRequest, pool: &PgPool) -> Result<Uuid, AuthError> {
+    if let Some(session) = request.extensions().get::<SessionUser>() {
+        tracing::Span::current().record("user_id", &tracing::field::display(&session.user_id));
+        return Ok(session.user_id);
+    }
+
+    let credentials =
+        basic_authentication(request.headers()).map_err(AuthError::InvalidCredentials)?;
+    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+
+    let user_id = verify_credentials(credentials, request, pool).await?;
+    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+
+    Ok(user_id)
+}
+
+/// Checks a username and password, unless the `LoginThrottle` says the
+/// username or the client has to wait after too many failures.
+pub async fn verify_credentials(
+    credentials: Credentials,
+    request: &HttpRequest,
+    pool: &PgPool,
+) -> Result<Uuid, AuthError> {
+    let throttle = request
+        .app_data::<web::Data<LoginThrottle>>()
+        .context("the login throttle is not configured")?;
+    let ip = throttle.client_ip(request);
+    let username = credentials.username.clone();
+    tracing::Span::current().record("username", &tracing::field::display(&username));
+
+    let attempt = throttle.begin(&username, ip, pool).await?;
+    match validate_credentials(credentials, pool).await {
+        Ok(user_id) => {
+            attempt.record_success().await?;
+            Ok(user_id)
+        }
+        Err(AuthError::InvalidCredentials(e)) => {
+            attempt.record_failure().await?;
+            Err(AuthError::InvalidCredentials(e))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("Authorization")?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    let header_value = headers
+        .get("Authorization")
+        .context("The 'Authorization' header was missing")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was not 'Basic'")?;
+    let decoded_bytes = base64::decode_config(base64encoded_segment, base64::STANDARD)
+        .context("failed to base64 decode credentials")?;
+    let decoded_credentials =
+        String::from_utf8(decoded_bytes).context("decoded credential string is not valid URF-8")?;
+
+    let mut credentials = decoded_credentials.splitn(2, ':');
+    let username = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("a username must be provided for basic auth"))?
+        .to_string();
+    let password = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("a password must be provided for basic auth"))?
+        .to_string();
+
+    Ok(Credentials { username, password })
+}
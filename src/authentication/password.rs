@@ -0,0 +1,120 @@
+use anyhow::Context;
+use argon2::{
+    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher,
+    PasswordVerifier, Version,
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::AuthError;
+use crate::{common::spawn_blocking_with_tracing, domain::NewPassword};
+
+#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+pub(super) async fn validate_credentials(
+    credentials: Credentials,
+    pool: &PgPool,
+) -> Result<uuid::Uuid, AuthError> {
+    let mut user_id = None;
+    let mut expected_password_hash = "$argon2id$v=19$m=15000,t=2,p=1$\
+    gZiV/M1gPc22ElAH/Jh1Hw$\
+    CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
+        .to_string();
+
+    if let Some((stored_user_id, stored_password_hash)) = get_stored_credentials(&credentials, pool)
+        .await
+        .map_err(AuthError::UnexpectedError)?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+
+    spawn_blocking_with_tracing(move || {
+        verify_password_hash(expected_password_hash, credentials.password)
+    })
+    .await
+    .context("failed to spawn blocking task")
+    .map_err(AuthError::UnexpectedError)?
+    .context("invalid password")
+    .map_err(AuthError::InvalidCredentials)?;
+
+    user_id.ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("unknown username")))
+}
+
+#[tracing::instrument(
+    name = "verify password hash",
+    skip(expected_password_hash, password_candidate)
+)]
+fn verify_password_hash(
+    expected_password_hash: String,
+    password_candidate: String,
+) -> Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(&expected_password_hash)
+        .context("failed to parse hash in PHC string format")
+        .map_err(AuthError::UnexpectedError)?;
+
+    Argon2::default()
+        .verify_password(password_candidate.as_bytes(), &expected_password_hash)
+        .context("invalid password")
+        .map_err(AuthError::InvalidCredentials)
+}
+
+#[tracing::instrument(name = "changing password", skip(password, executor))]
+pub async fn change_password<'e, E>(
+    user_id: Uuid,
+    password: NewPassword,
+    executor: E,
+) -> Result<(), anyhow::Error>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await
+        .context("failed to spawn blocking task")??;
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1 WHERE user_id = $2",
+        password_hash,
+        user_id
+    )
+    .execute(executor)
+    .await
+    .context("failed to change the user's password")?;
+    Ok(())
+}
+
+fn compute_password_hash(password: NewPassword) -> Result<String, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(15000, 2, 1, None).unwrap(),
+    )
+    .hash_password(password.as_ref().as_bytes(), &salt)
+    .context("failed to hash password")?
+    .to_string();
+    Ok(password_hash)
+}
+
+#[tracing::instrument(name = "retrieving user from database", skip(pool, credentials))]
+async fn get_stored_credentials(
+    credentials: &Credentials,
+    pool: &sqlx::Pool<sqlx::Postgres>,
+) -> Result<Option<(Uuid, String)>, anyhow::Error> {
+    let row: Option<_> = sqlx::query!(
+        r#"
+        SELECT user_id, password_hash
+        FROM users
+        WHERE username = $1
+        "#,
+        credentials.username
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to perform query to retrieve stored credentials")?
+    .map(|row| (row.user_id, row.password_hash));
+    Ok(row)
+}
+
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
@@ -0,0 +1,349 @@
+use std::{net::IpAddr, time::Duration};
+
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+
+use super::AuthError;
+use crate::configuration::LoginThrottleSettings;
+
+/// Slows down password guessing. Failed attempts are counted per username and
+/// per client address: past a few free failures every attempt has to wait for
+/// a delay that doubles with each failure, until enough failures lock the
+/// username or address out for a while.
+pub struct LoginThrottle {
+    settings: LoginThrottleSettings,
+}
+
+#[derive(Clone, Copy)]
+enum Subject<'a> {
+    Username(&'a str),
+    Ip(IpAddr),
+}
+
+impl Subject<'_> {
+    fn kind(&self) -> &'static str {
+        match self {
+            Subject::Username(_) => "username",
+            Subject::Ip(_) => "ip",
+        }
+    }
+
+    fn value(&self) -> String {
+        match self {
+            Subject::Username(username) => username.to_string(),
+            Subject::Ip(ip) => ip.to_string(),
+        }
+    }
+}
+
+impl LoginThrottle {
+    pub fn new(settings: LoginThrottleSettings) -> Self {
+        Self { settings }
+    }
+
+    /// The address failures are counted against. `X-Forwarded-For` is only
+    /// read when the request comes from a trusted proxy, and then from the
+    /// right: entries further left were written by the client and can be forged.
+    pub fn client_ip(&self, request: &HttpRequest) -> Option<IpAddr> {
+        let peer = request.peer_addr()?.ip();
+        let trusted_proxies = &self.settings.trusted_proxies;
+        if !trusted_proxies.contains(&peer) {
+            return Some(peer);
+        }
+        let forwarded_for = request
+            .headers()
+            .get_all("X-Forwarded-For")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect::<Vec<_>>();
+        let mut client = peer;
+        for address in forwarded_for.into_iter().rev() {
+            if !trusted_proxies.contains(&client) {
+                break;
+            }
+            match address.trim().parse::<IpAddr>() {
+                Ok(address) => client = address,
+                Err(_) => break,
+            }
+        }
+        Some(client)
+    }
+
+    /// Fails with `AuthError::TooManyAttempts` while either the username or the
+    /// address has to wait before trying again. Otherwise the returned attempt
+    /// holds a lock on both until its outcome is recorded, so that concurrent
+    /// attempts wait for it instead of all getting past the check before the
+    /// first failure is counted.
+    pub async fn begin<'a>(
+        &'a self,
+        username: &'a str,
+        ip: Option<IpAddr>,
+        pool: &PgPool,
+    ) -> Result<LoginAttempt<'a>, AuthError> {
+        let now = Utc::now();
+        let forget_before = now
+            - chrono::Duration::from_std(self.settings.lockout())
+                .context("lockout duration is out of range")?;
+        // Failures older than a lockout no longer count, drop them while we are
+        // here. Outside of the attempt's transaction, as it touches other subjects.
+        sqlx::query!(
+            r#"
+            DELETE FROM failed_logins
+            WHERE last_failure_at < $1 AND (blocked_until IS NULL OR blocked_until < $2)
+            "#,
+            forget_before,
+            now
+        )
+        .execute(pool)
+        .await
+        .context("failed to forget old failed logins")?;
+
+        let mut transaction = pool
+            .begin()
+            .await
+            .context("failed to start a login attempt")?;
+        // Always the username first, then the address, so that two attempts
+        // can never wait on each other.
+        let subjects = std::iter::once(Subject::Username(username)).chain(ip.map(Subject::Ip));
+        for subject in subjects {
+            // `pg_advisory_xact_lock` returns `void`, which the checked macros
+            // cannot describe.
+            sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+                .bind(format!("{}:{}", subject.kind(), subject.value()))
+                .execute(&mut transaction)
+                .await
+                .context("failed to lock the login attempt")?;
+        }
+
+        // Waiting for the locks may have taken a while.
+        let now = Utc::now();
+        let blocked_until = sqlx::query!(
+            r#"
+            SELECT MAX(blocked_until) AS blocked_until
+            FROM failed_logins
+            WHERE ((kind = 'username' AND subject = $1) OR (kind = 'ip' AND subject = $2))
+                AND blocked_until > $3
+            "#,
+            username,
+            ip.map(|ip| ip.to_string()),
+            now
+        )
+        .fetch_one(&mut transaction)
+        .await
+        .context("failed to check for failed login attempts")?
+        .blocked_until;
+
+        match blocked_until {
+            Some(blocked_until) => {
+                let wait = (blocked_until - now)
+                    .to_std()
+                    .unwrap_or_else(|_| Duration::from_secs(0));
+                // `Retry-After` has a one second granularity, round up.
+                let wait_secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+                Err(AuthError::TooManyAttempts(Duration::from_secs(wait_secs)))
+            }
+            None => Ok(LoginAttempt {
+                throttle: self,
+                username,
+                ip,
+                transaction,
+            }),
+        }
+    }
+
+    fn block_for(&self, failures: u32, lockout_threshold: u32) -> Option<Duration> {
+        if failures >= lockout_threshold {
+            return Some(self.settings.lockout());
+        }
+        let delayed_failures = failures.checked_sub(self.settings.free_attempts)?;
+        if delayed_failures == 0 {
+            return None;
+        }
+        let factor = 2u32.saturating_pow(delayed_failures - 1);
+        Some(
+            self.settings
+                .base_delay()
+                .saturating_mul(factor)
+                .min(self.settings.lockout()),
+        )
+    }
+}
+
+/// A login attempt that got past `LoginThrottle::begin`. Dropping it without
+/// recording an outcome releases its locks without counting anything.
+pub struct LoginAttempt<'a> {
+    throttle: &'a LoginThrottle,
+    username: &'a str,
+    ip: Option<IpAddr>,
+    transaction: Transaction<'static, Postgres>,
+}
+
+impl LoginAttempt<'_> {
+    #[tracing::instrument(name = "recording a failed login", skip(self))]
+    pub async fn record_failure(mut self) -> Result<(), anyhow::Error> {
+        let settings = &self.throttle.settings;
+        let now = Utc::now();
+        let forget_before = now
+            - chrono::Duration::from_std(settings.lockout())
+                .context("lockout duration is out of range")?;
+
+        let subjects =
+            std::iter::once(Subject::Username(self.username)).chain(self.ip.map(Subject::Ip));
+        for subject in subjects {
+            let failures = sqlx::query!(
+                r#"
+                INSERT INTO failed_logins (kind, subject, failures, last_failure_at)
+                VALUES ($1, $2, 1, $3)
+                ON CONFLICT (kind, subject) DO UPDATE SET
+                    failures = CASE
+                        WHEN failed_logins.last_failure_at < $4 THEN 1
+                        ELSE failed_logins.failures + 1
+                    END,
+                    last_failure_at = $3
+                RETURNING failures
+                "#,
+                subject.kind(),
+                subject.value(),
+                now,
+                forget_before
+            )
+            .fetch_one(&mut self.transaction)
+            .await
+            .context("failed to record a failed login")?
+            .failures as u32;
+
+            let threshold = match subject {
+                Subject::Username(_) => settings.username_lockout_threshold,
+                Subject::Ip(_) => settings.ip_lockout_threshold,
+            };
+            if let Some(block) = self.throttle.block_for(failures, threshold) {
+                if failures >= threshold {
+                    tracing::warn!(
+                        kind = subject.kind(),
+                        failures,
+                        lockout_secs = block.as_secs(),
+                        "locking out after too many failed login attempts"
+                    );
+                }
+                let blocked_until = now
+                    + chrono::Duration::from_std(block).context("login delay is out of range")?;
+                sqlx::query!(
+                    r#"
+                    UPDATE failed_logins SET blocked_until = $3
+                    WHERE kind = $1 AND subject = $2
+                    "#,
+                    subject.kind(),
+                    subject.value(),
+                    blocked_until
+                )
+                .execute(&mut self.transaction)
+                .await
+                .context("failed to delay further login attempts")?;
+            }
+        }
+        self.transaction
+            .commit()
+            .await
+            .context("failed to record a failed login")
+    }
+
+    /// A successful login clears the failures of the username. Those of the
+    /// address are kept, or logging into one's own account would reset them.
+    pub async fn record_success(mut self) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            "DELETE FROM failed_logins WHERE kind = 'username' AND subject = $1",
+            self.username
+        )
+        .execute(&mut self.transaction)
+        .await
+        .context("failed to clear failed logins")?;
+        self.transaction
+            .commit()
+            .await
+            .context("failed to clear failed logins")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, time::Duration};
+
+    use actix_web::test::TestRequest;
+
+    use super::LoginThrottle;
+    use crate::configuration::LoginThrottleSettings;
+
+    fn throttle() -> LoginThrottle {
+        LoginThrottle::new(LoginThrottleSettings {
+            free_attempts: 3,
+            base_delay_millis: 1000,
+            username_lockout_threshold: 10,
+            ip_lockout_threshold: 50,
+            lockout_secs: 900,
+            trusted_proxies: vec!["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()],
+        })
+    }
+
+    fn client_ip(peer: &str, forwarded_for: &str) -> IpAddr {
+        let request = TestRequest::default()
+            .peer_addr(format!("{}:4000", peer).parse().unwrap())
+            .insert_header(("X-Forwarded-For", forwarded_for))
+            .to_http_request();
+        throttle().client_ip(&request).unwrap()
+    }
+
+    #[test]
+    fn the_first_failures_are_free() {
+        for failures in 1..=3 {
+            assert_eq!(throttle().block_for(failures, 10), None);
+        }
+    }
+
+    #[test]
+    fn delays_double_with_each_failure() {
+        assert_eq!(throttle().block_for(4, 10), Some(Duration::from_secs(1)));
+        assert_eq!(throttle().block_for(5, 10), Some(Duration::from_secs(2)));
+        assert_eq!(throttle().block_for(9, 10), Some(Duration::from_secs(32)));
+        assert_eq!(throttle().block_for(30, 50), Some(Duration::from_secs(900)));
+    }
+
+    #[test]
+    fn reaching_the_threshold_locks_out() {
+        assert_eq!(throttle().block_for(10, 10), Some(Duration::from_secs(900)));
+        assert_eq!(throttle().block_for(50, 50), Some(Duration::from_secs(900)));
+    }
+
+    #[test]
+    fn forwarded_addresses_from_untrusted_peers_are_ignored() {
+        assert_eq!(
+            client_ip("203.0.113.7", "198.51.100.1"),
+            "203.0.113.7".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn trusted_proxies_report_the_client_address() {
+        assert_eq!(
+            client_ip("10.0.0.1", "198.51.100.1"),
+            "198.51.100.1".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            client_ip("10.0.0.1", "198.51.100.1, 10.0.0.2"),
+            "198.51.100.1".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn addresses_forged_left_of_the_proxies_are_ignored() {
+        assert_eq!(
+            client_ip("10.0.0.1", "192.0.2.99, 198.51.100.1"),
+            "198.51.100.1".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            client_ip("10.0.0.1", "not-an-address"),
+            "10.0.0.1".parse::<IpAddr>().unwrap()
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use std::fmt::Display;
+
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::common::error_chain_fmt;
+
+/// What a user is allowed to do. Each role can do everything the roles below
+/// it can.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Looks at subscriber statistics.
+    Viewer,
+    /// Reads subscribers, drafts, schedules and publishes newsletter issues.
+    Editor,
+    /// Manages subscribers on top of everything else.
+    Admin,
+}
+
+impl Role {
+    pub fn parse(role: &str) -> Option<Self> {
+        match role {
+            "viewer" => Some(Role::Viewer),
+            "editor" => Some(Role::Editor),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn grants(&self, permission: Permission) -> bool {
+        *self >= permission.required_role()
+    }
+}
+
+/// An action guarded by a role.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Permission {
+    ReadSubscriberStats,
+    ReadSubscribers,
+    PublishNewsletters,
+    ManageSubscribers,
+}
+
+impl Permission {
+    fn required_role(&self) -> Role {
+        match self {
+            Permission::ReadSubscriberStats => Role::Viewer,
+            Permission::ReadSubscribers => Role::Editor,
+            Permission::PublishNewsletters => Role::Editor,
+            Permission::ManageSubscribers => Role::Admin,
+        }
+    }
+}
+
+impl Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Permission::ReadSubscriberStats => "read subscriber statistics",
+            Permission::ReadSubscribers => "read subscribers",
+            Permission::PublishNewsletters => "publish newsletters",
+            Permission::ManageSubscribers => "manage subscribers",
+        })
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum AuthorizationError {
+    #[error("You are not allowed to {0}")]
+    Forbidden(Permission),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for AuthorizationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Checks that an authenticated user holds a role granting `permission`.
+#[tracing::instrument(name = "authorizing", skip(pool))]
+pub async fn authorize(
+    user_id: Uuid,
+    permission: Permission,
+    pool: &PgPool,
+) -> Result<(), AuthorizationError> {
+    let roles = get_roles(user_id, pool).await?;
+    if roles.iter().any(|role| role.grants(permission)) {
+        Ok(())
+    } else {
+        tracing::warn!(roles = ?roles, "forbidden action");
+        Err(AuthorizationError::Forbidden(permission))
+    }
+}
+
+pub async fn get_roles(user_id: Uuid, pool: &PgPool) -> Result<Vec<Role>, anyhow::Error> {
+    sqlx::query!("SELECT role FROM user_roles WHERE user_id = $1", user_id)
+        .fetch_all(pool)
+        .await
+        .context("failed to retrieve the user's roles")?
+        .into_iter()
+        .map(|r| Role::parse(&r.role).with_context(|| format!("unknown role {}", r.role)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Permission, Role};
+
+    #[test]
+    fn roles_include_the_permissions_of_lower_roles() {
+        assert!(Role::Viewer.grants(Permission::ReadSubscriberStats));
+        assert!(!Role::Viewer.grants(Permission::ReadSubscribers));
+        assert!(!Role::Viewer.grants(Permission::PublishNewsletters));
+        assert!(Role::Editor.grants(Permission::ReadSubscribers));
+        assert!(Role::Editor.grants(Permission::PublishNewsletters));
+        assert!(!Role::Editor.grants(Permission::ManageSubscribers));
+        assert!(Role::Admin.grants(Permission::ManageSubscribers));
+    }
+
+    #[test]
+    fn roles_round_trip_through_their_names() {
+        for role in [Role::Viewer, Role::Editor, Role::Admin] {
+            assert_eq!(Role::parse(role.as_str()), Some(role));
+        }
+        assert_eq!(Role::parse("owner"), None);
+    }
+}
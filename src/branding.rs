@@ -0,0 +1,70 @@
+use crate::{configuration::BrandingSettings, template::escape_html};
+
+/// Wraps `content_html` in a standalone page styled with the configured branding.
+/// `content_html` is inserted as-is, everything taken from the settings is escaped.
+pub fn render_page(branding: &BrandingSettings, heading: &str, content_html: &str) -> String {
+    let logo = branding
+        .logo_url
+        .as_deref()
+        .map(|url| {
+            format!(
+                r#"<img src="{}" alt="{}" style="max-height: 64px">"#,
+                escape_html(url),
+                escape_html(&branding.title)
+            )
+        })
+        .unwrap_or_default();
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>{title} - {heading}</title></head>
+<body style="background-color: {background}; font-family: sans-serif">
+<main style="max-width: 480px; margin: 48px auto; text-align: center">
+{logo}
+<h1 style="color: {primary}">{heading}</h1>
+{content}
+</main>
+</body>
+</html>"#,
+        title = escape_html(&branding.title),
+        heading = escape_html(heading),
+        background = escape_html(&branding.background_color),
+        primary = escape_html(&branding.primary_color),
+        logo = logo,
+        content = content_html,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render_page;
+    use crate::configuration::BrandingSettings;
+
+    fn branding(logo_url: Option<&str>) -> BrandingSettings {
+        BrandingSettings {
+            title: "Zero <2> Prod".to_string(),
+            logo_url: logo_url.map(str::to_string),
+            primary_color: "#123456".to_string(),
+            background_color: "#ffffff".to_string(),
+        }
+    }
+
+    #[test]
+    fn pages_use_the_configured_branding() {
+        let page = render_page(
+            &branding(Some("https://example.com/logo.png")),
+            "Done",
+            "<p>ok</p>",
+        );
+        assert!(page.contains("<title>Zero &lt;2&gt; Prod - Done</title>"));
+        assert!(page.contains(r#"<img src="https://example.com/logo.png""#));
+        assert!(page.contains("color: #123456"));
+        assert!(page.contains("<p>ok</p>"));
+    }
+
+    #[test]
+    fn the_logo_is_optional() {
+        let page = render_page(&branding(None), "Done", "");
+        assert!(!page.contains("<img"));
+    }
+}
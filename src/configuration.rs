@@ -1,14 +1,19 @@
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 use std::convert::{TryFrom, TryInto};
+use std::net::IpAddr;
 
-use crate::domain::SubscriberEmail;
+use crate::{domain::SubscriberEmail, email_client::EmailClient};
+
+const MIN_HMAC_SECRET_LENGTH: usize = 32;
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub branding: BrandingSettings,
+    pub login_throttle: LoginThrottleSettings,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -17,6 +22,86 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub idempotency_key_expiration_secs: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub subscription_token_expiration_secs: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub pending_subscriber_retention_secs: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub session_expiration_secs: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub password_reset_token_expiration_secs: u64,
+    /// Never committed outside of `local.yaml`, `get_config` refuses to start
+    /// without one.
+    #[serde(default)]
+    pub hmac_secret: String,
+}
+
+impl ApplicationSettings {
+    pub fn idempotency_key_expiration(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.idempotency_key_expiration_secs)
+    }
+
+    pub fn subscription_token_expiration(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.subscription_token_expiration_secs)
+    }
+
+    pub fn pending_subscriber_retention(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.pending_subscriber_retention_secs)
+    }
+
+    pub fn session_expiration(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.session_expiration_secs)
+    }
+
+    pub fn password_reset_token_expiration(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.password_reset_token_expiration_secs)
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct BrandingSettings {
+    pub title: String,
+    pub logo_url: Option<String>,
+    pub primary_color: String,
+    pub background_color: String,
+}
+
+/// How failed logins slow down further attempts, see `authentication::LoginThrottle`.
+#[derive(serde::Deserialize, Clone)]
+pub struct LoginThrottleSettings {
+    /// Failures allowed before attempts start being delayed.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub free_attempts: u32,
+    /// Delay after the first failure past the free ones, doubled with each
+    /// further failure.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub base_delay_millis: u64,
+    /// Failures for a username that lock it out.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub username_lockout_threshold: u32,
+    /// Failures from a client address that lock it out, higher as several
+    /// users can share an address.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub ip_lockout_threshold: u32,
+    /// How long a lockout lasts, and how long failures are remembered for.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub lockout_secs: u64,
+    /// Load balancers allowed to report the client address in
+    /// `X-Forwarded-For`. Headers from anyone else are ignored.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl LoginThrottleSettings {
+    pub fn base_delay(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.base_delay_millis)
+    }
+
+    pub fn lockout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.lockout_secs)
+    }
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -28,6 +113,9 @@ pub struct DatabaseSettings {
     pub host: String,
     pub database_name: String,
     pub require_ssl: bool,
+    /// Upper bound on the pool size.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_connections: u32,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -35,12 +123,26 @@ pub struct EmailClientSettings {
     pub base_url: String,
     pub sender_email: String,
     pub auth_token: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub messages_per_second: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_concurrent_sends: usize,
 }
 
 impl EmailClientSettings {
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         self.sender_email.clone().try_into()
     }
+
+    pub fn client(self) -> EmailClient {
+        let sender_email = self.sender().expect("should be a valid email address");
+        EmailClient::new(
+            self.base_url,
+            sender_email,
+            self.auth_token,
+            self.messages_per_second,
+        )
+    }
 }
 
 pub fn get_config() -> Result<Settings, config::ConfigError> {
@@ -61,7 +163,17 @@ pub fn get_config() -> Result<Settings, config::ConfigError> {
     )?;
     settings.merge(config::Environment::with_prefix("app").separator("__"))?;
 
-    settings.try_into()
+    let settings: Settings = settings.try_into()?;
+    // The secret signs sessions and unsubscribe links, a short or guessable one
+    // would let anyone forge them.
+    if settings.application.hmac_secret.len() < MIN_HMAC_SECRET_LENGTH {
+        return Err(config::ConfigError::Message(format!(
+            "application.hmac_secret must be at least {} characters long, \
+            set it with APP_APPLICATION__HMAC_SECRET",
+            MIN_HMAC_SECRET_LENGTH
+        )));
+    }
+    Ok(settings)
 }
 
 enum Environment {
@@ -69,6 +181,7 @@ enum Environment {
     Production,
 }
 
+#[allow(clippy::to_string_trait_impl)]
 impl ToString for Environment {
     fn to_string(&self) -> String {
         match self {
@@ -0,0 +1,64 @@
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+pub struct ConfirmedSubscriber {
+    pub id: Uuid,
+    pub email: String,
+}
+
+pub struct ConfirmedSubscriberReader {
+    list_id: Uuid,
+    after: Option<Uuid>,
+    page_size: i64,
+}
+
+impl ConfirmedSubscriberReader {
+    pub fn resume_after(list_id: Uuid, after: Option<Uuid>, page_size: i64) -> Self {
+        Self {
+            list_id,
+            after,
+            page_size,
+        }
+    }
+
+    pub fn cursor(&self) -> Option<Uuid> {
+        self.after
+    }
+
+    pub fn page_size(&self) -> i64 {
+        self.page_size
+    }
+
+    #[tracing::instrument(
+        name = "reading a page of confirmed subscribers",
+        skip(self, transaction)
+    )]
+    pub async fn next_page(
+        &mut self,
+        transaction: &mut Transaction<'_, Postgres>,
+    ) -> Result<Vec<ConfirmedSubscriber>, sqlx::Error> {
+        let page = sqlx::query_as!(
+            ConfirmedSubscriber,
+            r#"
+            SELECT subscriptions.id, subscriptions.email
+            FROM subscriptions
+            JOIN list_subscriptions ON list_subscriptions.subscriber_id = subscriptions.id
+            WHERE list_subscriptions.list_id = $1
+                AND list_subscriptions.status = 'confirmed'
+                AND ($2::uuid IS NULL OR subscriptions.id > $2)
+            ORDER BY subscriptions.id
+            LIMIT $3
+            "#,
+            self.list_id,
+            self.after,
+            self.page_size
+        )
+        .fetch_all(transaction)
+        .await?;
+
+        if let Some(last) = page.last() {
+            self.after = Some(last.id);
+        }
+        Ok(page)
+    }
+}
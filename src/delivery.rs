@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// What a background sender did on one pass over its queue.
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+/// Whether a failed send is worth retrying, as opposed to one the email
+/// provider will keep refusing.
+pub(crate) fn is_transient(error: &reqwest::Error) -> bool {
+    if error.is_timeout() || error.is_connect() {
+        return true;
+    }
+    match error.status() {
+        Some(status) => status.is_server_error() || status.as_u16() == 429,
+        None => error.is_request(),
+    }
+}
+
+/// Exponential backoff with jitter, so that a provider outage does not end
+/// with every queued email being retried at once.
+pub(crate) fn retry_delay(n_attempts: i16) -> Duration {
+    let exponent = n_attempts.saturating_sub(1).clamp(0, 16) as u32;
+    let backoff = BASE_RETRY_DELAY
+        .saturating_mul(2u32.saturating_pow(exponent))
+        .min(MAX_RETRY_DELAY);
+    let jitter = rand::thread_rng().gen_range(0..=backoff.as_millis() as u64 / 2);
+    backoff / 2 + Duration::from_millis(jitter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{retry_delay, BASE_RETRY_DELAY, MAX_RETRY_DELAY};
+
+    #[test]
+    fn retry_delay_grows_exponentially() {
+        for n_attempts in 1..5 {
+            let backoff = BASE_RETRY_DELAY * 2u32.pow(n_attempts as u32 - 1);
+            let delay = retry_delay(n_attempts);
+            assert!(delay >= backoff / 2 && delay <= backoff);
+        }
+    }
+
+    #[test]
+    fn retry_delay_is_capped() {
+        let delay = retry_delay(i16::MAX);
+        assert!(delay >= MAX_RETRY_DELAY / 2 && delay <= MAX_RETRY_DELAY);
+    }
+}
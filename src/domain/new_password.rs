@@ -0,0 +1,67 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+const MIN_LENGTH: usize = 12;
+const MAX_LENGTH: usize = 128;
+
+/// A password that satisfies the password policy, ready to be hashed.
+#[derive(Debug)]
+pub struct NewPassword(String);
+
+impl NewPassword {
+    /// Checks `password` against the policy and against the confirmation typed
+    /// alongside it.
+    pub fn parse(password: String, confirmation: &str) -> Result<Self, String> {
+        if password != confirmation {
+            return Err("You entered two different new passwords.".to_string());
+        }
+        let length = password.graphemes(true).count();
+        if length < MIN_LENGTH {
+            return Err(format!(
+                "The new password must be at least {} characters long.",
+                MIN_LENGTH
+            ));
+        }
+        if length > MAX_LENGTH {
+            return Err(format!(
+                "The new password must be at most {} characters long.",
+                MAX_LENGTH
+            ));
+        }
+        Ok(Self(password))
+    }
+}
+
+impl AsRef<str> for NewPassword {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+
+    use super::NewPassword;
+
+    #[test]
+    fn passwords_within_the_length_bounds_are_accepted() {
+        let password = "a".repeat(12);
+        assert_ok!(NewPassword::parse(password.clone(), &password));
+        let password = "a".repeat(128);
+        assert_ok!(NewPassword::parse(password.clone(), &password));
+    }
+
+    #[test]
+    fn passwords_outside_the_length_bounds_are_rejected() {
+        let password = "a".repeat(11);
+        assert_err!(NewPassword::parse(password.clone(), &password));
+        let password = "a".repeat(129);
+        assert_err!(NewPassword::parse(password.clone(), &password));
+    }
+
+    #[test]
+    fn passwords_must_match_their_confirmation() {
+        let password = "a".repeat(20);
+        assert_err!(NewPassword::parse(password, &"b".repeat(20)));
+    }
+}
@@ -2,7 +2,7 @@ use std::{convert::TryInto, fmt::Display};
 
 use validator::validate_email;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SubscriberEmail(String);
 
 impl Display for SubscriberEmail {
@@ -2,7 +2,7 @@ use std::convert::TryInto;
 
 use unicode_segmentation::UnicodeSegmentation;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SubscriberName(String);
 
 impl AsRef<str> for SubscriberName {
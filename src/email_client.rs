@@ -1,16 +1,28 @@
-use reqwest::Client;
+use std::{sync::Arc, time::Duration};
 
-use crate::domain::SubscriberEmail;
+use chrono::{DateTime, Utc};
+use reqwest::{header::HeaderMap, Client, StatusCode};
 
+use crate::{domain::SubscriberEmail, rate_limiter::RateLimiter};
+
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+#[derive(Clone)]
 pub struct EmailClient {
     client: Client,
     base_url: String,
     sender: SubscriberEmail,
     auth_token: String,
+    rate_limiter: Arc<RateLimiter>,
 }
 
 impl EmailClient {
-    pub fn new(base_url: String, sender: SubscriberEmail, auth_token: String) -> Self {
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        auth_token: String,
+        messages_per_second: u32,
+    ) -> Self {
         let client = Client::builder()
             .timeout(std::time::Duration::from_secs(10))
             .build()
@@ -20,6 +32,7 @@ impl EmailClient {
             base_url,
             sender,
             auth_token,
+            rate_limiter: Arc::new(RateLimiter::new(messages_per_second)),
         }
     }
 
@@ -29,6 +42,18 @@ impl EmailClient {
         subject: &str,
         html_content: &str,
         text_content: &str,
+    ) -> Result<(), reqwest::Error> {
+        self.send_email_with_headers(recipient, subject, html_content, text_content, &[])
+            .await
+    }
+
+    pub async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader<'_>],
     ) -> Result<(), reqwest::Error> {
         let dest_url = format!("{}/email", self.base_url);
         let request_body = SendEmailRequest {
@@ -37,18 +62,38 @@ impl EmailClient {
             subject,
             html_body: html_content,
             text_body: text_content,
+            headers,
         };
-        self.client
+        self.rate_limiter.acquire().await;
+        let response = self
+            .client
             .post(&dest_url)
             .header("X-Postmark-Server-Token", &self.auth_token)
             .json(&request_body)
             .send()
-            .await?
-            .error_for_status()?;
+            .await?;
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = retry_after(response.headers()).unwrap_or(DEFAULT_RETRY_AFTER);
+            tracing::warn!(
+                retry_after_secs = retry_after.as_secs_f64(),
+                "email provider is rate limiting us, pausing all sends"
+            );
+            self.rate_limiter.pause_for(retry_after);
+        }
+        response.error_for_status()?;
         Ok(())
     }
 }
 
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let retry_at = DateTime::parse_from_rfc2822(value).ok()?;
+    (retry_at.with_timezone(&Utc) - Utc::now()).to_std().ok()
+}
+
 #[derive(serde::Serialize)]
 #[serde(rename_all = "PascalCase")]
 struct SendEmailRequest<'a> {
@@ -57,6 +102,15 @@ struct SendEmailRequest<'a> {
     subject: &'a str,
     html_body: &'a str,
     text_body: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    headers: &'a [EmailHeader<'a>],
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct EmailHeader<'a> {
+    pub name: &'a str,
+    pub value: &'a str,
 }
 
 #[cfg(test)]
@@ -75,7 +129,7 @@ mod tests {
 
     use crate::domain::SubscriberEmail;
 
-    use super::EmailClient;
+    use super::{EmailClient, EmailHeader};
 
     struct SendEmailBodyMatcher;
 
@@ -110,7 +164,7 @@ mod tests {
     #[tokio::test]
     async fn send_email_fires_a_request_to_base_url() {
         let mock_server = MockServer::start().await;
-        let email_client = EmailClient::new(mock_server.uri(), email(), Faker.fake());
+        let email_client = EmailClient::new(mock_server.uri(), email(), Faker.fake(), 100);
 
         Mock::given(header_exists("X-Postmark-Server-Token"))
             .and(header("Content-Type", "application/json"))
@@ -128,10 +182,74 @@ mod tests {
         assert_ok!(outcome);
     }
 
+    #[tokio::test]
+    async fn send_email_with_headers_passes_the_headers_through() {
+        let mock_server = MockServer::start().await;
+        let email_client = EmailClient::new(mock_server.uri(), email(), Faker.fake(), 100);
+
+        Mock::given(path("/email"))
+            .and(|request: &wiremock::Request| {
+                let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+                body["Headers"]
+                    == serde_json::json!([
+                        { "Name": "List-Unsubscribe", "Value": "<https://example.com>" }
+                    ])
+            })
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email_with_headers(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                &[EmailHeader {
+                    name: "List-Unsubscribe",
+                    value: "<https://example.com>",
+                }],
+            )
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn a_429_pauses_sends_until_retry_after_has_elapsed() {
+        let mock_server = MockServer::start().await;
+        let email_client = EmailClient::new(mock_server.uri(), email(), Faker.fake(), 100);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+        assert_err!(outcome);
+
+        let started = std::time::Instant::now();
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+        assert_ok!(outcome);
+        assert!(started.elapsed() >= std::time::Duration::from_millis(900));
+    }
+
     #[tokio::test]
     async fn send_email_fails_if_server_returns_500() {
         let mock_server = MockServer::start().await;
-        let email_client = EmailClient::new(mock_server.uri(), email(), Faker.fake());
+        let email_client = EmailClient::new(mock_server.uri(), email(), Faker.fake(), 100);
 
         Mock::given(any())
             .respond_with(ResponseTemplate::new(500))
@@ -0,0 +1,197 @@
+use std::{convert::TryInto, time::Duration};
+
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::{
+    delivery::{is_transient, retry_delay, ExecutionOutcome},
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+};
+
+const MAX_SEND_ATTEMPTS: i16 = 5;
+
+pub struct OutboxDispatcher {
+    pool: PgPool,
+    email_client: EmailClient,
+}
+
+impl OutboxDispatcher {
+    pub fn new(pool: PgPool, email_client: EmailClient) -> Self {
+        Self { pool, email_client }
+    }
+
+    pub async fn run_until_stopped(self) {
+        loop {
+            match try_dispatch_email(&self.pool, &self.email_client).await {
+                Ok(ExecutionOutcome::EmptyQueue) => {
+                    actix_web::rt::time::sleep(Duration::from_secs(1)).await;
+                }
+                Ok(ExecutionOutcome::TaskCompleted) => {}
+                Err(_) => {
+                    actix_web::rt::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+}
+
+pub struct OutgoingEmail<'a> {
+    pub recipient: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_body: &'a str,
+    pub text_body: &'a str,
+}
+
+// Emails are written as part of the caller's transaction, so they only go out
+// once whatever they refer to has actually been committed.
+#[tracing::instrument(name = "adding an email to the outbox", skip(email, transaction))]
+pub async fn enqueue_email(
+    email: OutgoingEmail<'_>,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Uuid, sqlx::Error> {
+    let email_id = Uuid::new_v4();
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO email_outbox (
+            email_id,
+            recipient,
+            subject,
+            html_body,
+            text_body,
+            status,
+            n_attempts,
+            execute_after,
+            created_at
+        )
+        VALUES ($1, $2, $3, $4, $5, 'pending', 0, $6, $6)
+        "#,
+        email_id,
+        email.recipient.as_ref(),
+        email.subject,
+        email.html_body,
+        email.text_body,
+        now
+    )
+    .execute(transaction)
+    .await?;
+    Ok(email_id)
+}
+
+struct OutboxEmail {
+    email_id: Uuid,
+    recipient: String,
+    subject: String,
+    html_body: String,
+    text_body: String,
+    n_attempts: i16,
+}
+
+#[tracing::instrument(
+    name = "dispatching an email from the outbox",
+    skip(pool, email_client),
+    fields(email_id = tracing::field::Empty, n_attempts = tracing::field::Empty),
+    err
+)]
+pub async fn try_dispatch_email(
+    pool: &PgPool,
+    email_client: &EmailClient,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to retrieve connection from pool")?;
+
+    let email = sqlx::query_as!(
+        OutboxEmail,
+        r#"
+        SELECT email_id, recipient, subject, html_body, text_body, n_attempts
+        FROM email_outbox
+        WHERE status = 'pending' AND execute_after <= $1
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+        Utc::now()
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("failed to query the email outbox")?;
+    let email = match email {
+        Some(email) => email,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    Span::current()
+        .record("email_id", &display(email.email_id))
+        .record("n_attempts", &display(email.n_attempts));
+
+    let outcome = match TryInto::<SubscriberEmail>::try_into(email.recipient.clone()) {
+        Ok(recipient) => email_client
+            .send_email(
+                &recipient,
+                &email.subject,
+                &email.html_body,
+                &email.text_body,
+            )
+            .await
+            .map_err(|error| {
+                tracing::warn!(error.cause_chain = ?error, "failed to send an outbox email");
+                (is_transient(&error), error.to_string())
+            }),
+        Err(error) => Err((false, error)),
+    };
+
+    let n_attempts = email.n_attempts.saturating_add(1);
+    match outcome {
+        // Sent emails are removed rather than kept around, as they can carry
+        // secrets such as confirmation links.
+        Ok(()) => {
+            sqlx::query!(
+                "DELETE FROM email_outbox WHERE email_id = $1",
+                email.email_id
+            )
+            .execute(&mut transaction)
+            .await
+            .context("failed to remove a sent email from the outbox")?;
+        }
+        Err((transient, error)) => {
+            let (status, execute_after) = if transient && n_attempts < MAX_SEND_ATTEMPTS {
+                let delay = chrono::Duration::from_std(retry_delay(n_attempts))
+                    .context("retry delay is out of range")?;
+                ("pending", Utc::now() + delay)
+            } else {
+                tracing::error!("giving up on an outbox email");
+                ("failed", Utc::now())
+            };
+            // Emails we give up on are kept for inspection, but without their
+            // bodies: like sent ones, they can carry secrets.
+            sqlx::query!(
+                r#"
+                UPDATE email_outbox
+                SET status = $2, n_attempts = $3, last_error = $4, execute_after = $5,
+                    html_body = CASE WHEN $2 = 'failed' THEN '' ELSE html_body END,
+                    text_body = CASE WHEN $2 = 'failed' THEN '' ELSE text_body END
+                WHERE email_id = $1
+                "#,
+                email.email_id,
+                status,
+                n_attempts,
+                error,
+                execute_after
+            )
+            .execute(&mut transaction)
+            .await
+            .context("failed to record a failed outbox email")?;
+        }
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("failed to complete transaction")?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
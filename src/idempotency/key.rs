@@ -0,0 +1,55 @@
+use std::convert::TryInto;
+
+#[derive(Debug)]
+pub struct IdempotencyKey(String);
+
+impl TryInto<IdempotencyKey> for String {
+    type Error = String;
+
+    fn try_into(self) -> Result<IdempotencyKey, Self::Error> {
+        if self.is_empty() {
+            return Err("the idempotency key cannot be empty".to_string());
+        }
+        let max_length = 50;
+        if self.len() >= max_length {
+            return Err(format!(
+                "the idempotency key must be shorter than {} characters",
+                max_length
+            ));
+        }
+        Ok(IdempotencyKey(self))
+    }
+}
+
+impl AsRef<str> for IdempotencyKey {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use claim::{assert_err, assert_ok};
+
+    use super::IdempotencyKey;
+
+    #[test]
+    fn an_empty_key_is_rejected() {
+        let key = "".to_string();
+        assert_err!(TryInto::<IdempotencyKey>::try_into(key));
+    }
+
+    #[test]
+    fn a_50_character_key_is_rejected() {
+        let key = "a".repeat(50);
+        assert_err!(TryInto::<IdempotencyKey>::try_into(key));
+    }
+
+    #[test]
+    fn a_uuid_key_is_accepted() {
+        let key = uuid::Uuid::new_v4().to_string();
+        assert_ok!(TryInto::<IdempotencyKey>::try_into(key));
+    }
+}
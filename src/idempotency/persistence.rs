@@ -0,0 +1,176 @@
+use std::{
+    convert::{TryFrom, TryInto},
+    time::Duration,
+};
+
+use actix_http::{
+    header::{HeaderName, HeaderValue},
+    StatusCode,
+};
+use actix_web::{
+    body::{to_bytes, AnyBody},
+    HttpResponse,
+};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::IdempotencyKey;
+
+#[allow(clippy::large_enum_variant)]
+pub enum NextAction {
+    StartProcessing(Transaction<'static, Postgres>),
+    ReturnSavedResponse(HttpResponse),
+}
+
+#[tracing::instrument(name = "checking idempotency key", skip(pool, idempotency_key))]
+pub async fn try_processing(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    expiration: Duration,
+) -> Result<NextAction, anyhow::Error> {
+    delete_expired_keys(pool, user_id, expiration)
+        .await
+        .context("failed to delete expired idempotency keys")?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to retrieve connection from pool")?;
+
+    // A concurrent request holding the same key blocks this insert until it
+    // either commits its saved response or rolls back and frees the key.
+    let inserted_rows = sqlx::query!(
+        r#"
+        INSERT INTO idempotency (user_id, idempotency_key, created_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        Utc::now()
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to insert idempotency key")?
+    .rows_affected();
+
+    if inserted_rows > 0 {
+        Ok(NextAction::StartProcessing(transaction))
+    } else {
+        let saved_response = get_saved_response(pool, idempotency_key, user_id)
+            .await?
+            .context("expected a saved response for an idempotency key that already exists")?;
+        Ok(NextAction::ReturnSavedResponse(saved_response))
+    }
+}
+
+#[tracing::instrument(name = "deleting expired idempotency keys", skip(pool))]
+async fn delete_expired_keys(
+    pool: &PgPool,
+    user_id: Uuid,
+    expiration: Duration,
+) -> Result<(), anyhow::Error> {
+    let expired_before = Utc::now()
+        - chrono::Duration::from_std(expiration).context("expiration window is out of range")?;
+    sqlx::query!(
+        "DELETE FROM idempotency WHERE user_id = $1 AND created_at < $2",
+        user_id,
+        expired_before
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "retrieving saved response", skip(pool, idempotency_key))]
+async fn get_saved_response(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<Option<HttpResponse>, anyhow::Error> {
+    let saved_response = sqlx::query!(
+        r#"
+        SELECT
+            response_status_code as "response_status_code!",
+            response_header_names as "response_header_names!",
+            response_header_values as "response_header_values!",
+            response_body as "response_body!"
+        FROM idempotency
+        WHERE user_id = $1 AND idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref()
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to query saved response")?;
+
+    if let Some(row) = saved_response {
+        let status_code = StatusCode::from_u16(row.response_status_code.try_into()?)?;
+        let mut response = HttpResponse::build(status_code);
+        for (name, value) in row
+            .response_header_names
+            .into_iter()
+            .zip(row.response_header_values)
+        {
+            response.append_header((HeaderName::try_from(name)?, HeaderValue::try_from(value)?));
+        }
+        Ok(Some(response.body(row.response_body)))
+    } else {
+        Ok(None)
+    }
+}
+
+#[tracing::instrument(
+    name = "saving response for idempotency key",
+    skip(transaction, idempotency_key, http_response)
+)]
+pub async fn save_response(
+    mut transaction: Transaction<'static, Postgres>,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    http_response: HttpResponse,
+) -> Result<HttpResponse, anyhow::Error> {
+    let (response_head, body) = http_response.into_parts();
+    let body = to_bytes(body)
+        .await
+        .map_err(|e| anyhow::anyhow!("{}", e))
+        .context("failed to read response body")?;
+    let status_code = response_head.status().as_u16() as i16;
+    let (header_names, header_values): (Vec<String>, Vec<Vec<u8>>) = response_head
+        .headers()
+        .iter()
+        .map(|(name, value)| (name.as_str().to_owned(), value.as_bytes().to_owned()))
+        .unzip();
+
+    sqlx::query!(
+        r#"
+        UPDATE idempotency
+        SET
+            response_status_code = $3,
+            response_header_names = $4,
+            response_header_values = $5,
+            response_body = $6
+        WHERE user_id = $1 AND idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        status_code,
+        &header_names,
+        &header_values,
+        body.as_ref()
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to save response")?;
+
+    transaction
+        .commit()
+        .await
+        .context("failed to complete transaction")?;
+
+    Ok(response_head.set_body(AnyBody::from(body)))
+}
@@ -0,0 +1,421 @@
+use std::{collections::HashMap, convert::TryInto, time::Duration};
+
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::{
+    confirmed_subscribers::ConfirmedSubscriber,
+    delivery::{is_transient, retry_delay, ExecutionOutcome},
+    domain::SubscriberEmail,
+    email_client::{EmailClient, EmailHeader},
+    startup::HmacSecret,
+    template::{MergeFields, Template, TemplateError},
+    unsubscribe_token::UnsubscribeToken,
+};
+
+const MAX_DELIVERY_ATTEMPTS: i16 = 5;
+/// How long a claimed delivery is left alone before another worker assumes the
+/// one sending it died. Well past a send, including waits on the rate limiter.
+const DELIVERY_LEASE: Duration = Duration::from_secs(10 * 60);
+
+pub struct IssueDeliveryWorker {
+    pool: PgPool,
+    email_client: EmailClient,
+    base_url: String,
+    hmac_secret: HmacSecret,
+    max_concurrent_sends: usize,
+}
+
+impl IssueDeliveryWorker {
+    pub fn new(
+        pool: PgPool,
+        email_client: EmailClient,
+        base_url: String,
+        hmac_secret: HmacSecret,
+        max_concurrent_sends: usize,
+    ) -> Self {
+        Self {
+            pool,
+            email_client,
+            base_url,
+            hmac_secret,
+            max_concurrent_sends,
+        }
+    }
+
+    pub async fn run_until_stopped(self) {
+        // Each loop claims one task at a time, so running several of them side
+        // by side bounds the number of sends in flight.
+        let workers = (0..self.max_concurrent_sends.max(1)).map(|_| {
+            worker_loop(
+                &self.pool,
+                &self.email_client,
+                &self.base_url,
+                &self.hmac_secret,
+            )
+        });
+        futures::future::join_all(workers).await;
+    }
+}
+
+async fn worker_loop(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    hmac_secret: &HmacSecret,
+) {
+    loop {
+        match try_execute_task(pool, email_client, base_url, hmac_secret).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                actix_web::rt::time::sleep(Duration::from_secs(10)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+            Err(_) => {
+                actix_web::rt::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+enum DeliveryOutcome {
+    Delivered,
+    Retry(String),
+    DeadLetter(String),
+    /// The recipient left the list after the issue was queued.
+    Dropped,
+}
+
+#[tracing::instrument(
+    name = "delivering a queued newsletter issue",
+    skip(pool, email_client, base_url, hmac_secret),
+    fields(
+        newsletter_issue_id = tracing::field::Empty,
+        subscriber_email = tracing::field::Empty,
+        n_attempts = tracing::field::Empty
+    ),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    hmac_secret: &HmacSecret,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let task = match claim_task(pool).await? {
+        Some(task) => task,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    Span::current()
+        .record("newsletter_issue_id", &display(task.newsletter_issue_id))
+        .record("subscriber_email", &display(&task.subscriber_email))
+        .record("n_attempts", &display(task.n_attempts));
+
+    let outcome = match personalise_email(&task, base_url, hmac_secret, pool).await? {
+        Ok((recipient, issue, unsubscribe_url)) => match email_client
+            .send_email_with_headers(
+                &recipient,
+                &issue.title,
+                &issue.html_content,
+                &issue.text_content,
+                &[
+                    EmailHeader {
+                        name: "List-Unsubscribe",
+                        value: &format!("<{}>", unsubscribe_url),
+                    },
+                    EmailHeader {
+                        name: "List-Unsubscribe-Post",
+                        value: "List-Unsubscribe=One-Click",
+                    },
+                ],
+            )
+            .await
+        {
+            Ok(()) => DeliveryOutcome::Delivered,
+            Err(error) if is_transient(&error) => {
+                tracing::warn!(
+                    error.cause_chain = ?error,
+                    "failed to deliver issue to a confirmed subscriber, will retry"
+                );
+                DeliveryOutcome::Retry(error.to_string())
+            }
+            Err(error) => {
+                tracing::error!(
+                    error.cause_chain = ?error,
+                    "failed to deliver issue to a confirmed subscriber, giving up"
+                );
+                DeliveryOutcome::DeadLetter(error.to_string())
+            }
+        },
+        Err(outcome) => outcome,
+    };
+
+    record_attempt(pool, &task, outcome).await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+// The outer error aborts the attempt so it is retried once its lease expires,
+// while the inner one means this recipient must not be sent the issue.
+async fn personalise_email(
+    task: &DeliveryTask,
+    base_url: &str,
+    hmac_secret: &HmacSecret,
+    pool: &PgPool,
+) -> Result<Result<(SubscriberEmail, NewsletterIssue, String), DeliveryOutcome>, anyhow::Error> {
+    let recipient = match TryInto::<SubscriberEmail>::try_into(task.subscriber_email.clone()) {
+        Ok(recipient) => recipient,
+        Err(error) => {
+            tracing::warn!(
+                error.cause_chain = ?error,
+                "skipping a confirmed subscriber as their email was found to be invalid"
+            );
+            return Ok(Err(DeliveryOutcome::DeadLetter(error)));
+        }
+    };
+    let issue = get_issue(task.newsletter_issue_id, pool).await?;
+    let subscriber = match get_recipient(&task.subscriber_email, issue.list_id, pool).await? {
+        Some(subscriber) => subscriber,
+        None => {
+            tracing::info!("dropping a subscriber that is no longer confirmed on the list");
+            return Ok(Err(DeliveryOutcome::Dropped));
+        }
+    };
+
+    let unsubscribe_url = format!(
+        "{}/subscriptions/unsubscribe?token={}",
+        base_url,
+        UnsubscribeToken::new(subscriber.id, issue.list_id).sign(hmac_secret)
+    );
+    let fields = MergeFields {
+        name: &subscriber.name,
+        email: &task.subscriber_email,
+        unsubscribe_url: &unsubscribe_url,
+        attributes: &subscriber.attributes,
+    };
+    match issue.personalise(&fields) {
+        Ok(mut issue) => {
+            issue.html_content.push_str(&format!(
+                "<p><a href=\"{}\">Unsubscribe</a></p>",
+                unsubscribe_url
+            ));
+            issue
+                .text_content
+                .push_str(&format!("\n\nUnsubscribe: {}", unsubscribe_url));
+            Ok(Ok((recipient, issue, unsubscribe_url)))
+        }
+        Err(error) => {
+            tracing::error!(
+                error.cause_chain = ?error,
+                "skipping a subscriber as the issue template could not be rendered"
+            );
+            Ok(Err(DeliveryOutcome::DeadLetter(error.to_string())))
+        }
+    }
+}
+
+#[tracing::instrument(name = "enqueueing delivery tasks", skip(subscribers, transaction))]
+pub async fn enqueue_delivery_tasks(
+    newsletter_issue_id: Uuid,
+    subscribers: &[ConfirmedSubscriber],
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(), sqlx::Error> {
+    let emails: Vec<&str> = subscribers
+        .iter()
+        .map(|subscriber| subscriber.email.as_str())
+        .collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email,
+            status,
+            n_attempts,
+            execute_after
+        )
+        SELECT $1, email, 'pending', 0, $3
+        FROM UNNEST($2::TEXT[]) AS email
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+        &emails as &[&str],
+        Utc::now()
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_attempts: i16,
+}
+
+/// Marks a due task as in flight for `DELIVERY_LEASE` and counts the attempt,
+/// committing straight away so that no transaction is held during the send.
+#[tracing::instrument(name = "claiming a delivery task", skip(pool))]
+async fn claim_task(pool: &PgPool) -> Result<Option<DeliveryTask>, anyhow::Error> {
+    let now = Utc::now();
+    let lease = chrono::Duration::from_std(DELIVERY_LEASE).context("lease is out of range")?;
+    sqlx::query_as!(
+        DeliveryTask,
+        r#"
+        UPDATE issue_delivery_queue
+        SET status = 'in_flight', n_attempts = n_attempts + 1, execute_after = $2
+        WHERE (newsletter_issue_id, subscriber_email) = (
+            SELECT newsletter_issue_id, subscriber_email
+            FROM issue_delivery_queue
+            WHERE status IN ('pending', 'in_flight') AND execute_after <= $1
+            FOR UPDATE
+            SKIP LOCKED
+            LIMIT 1
+        )
+        RETURNING newsletter_issue_id, subscriber_email, n_attempts
+        "#,
+        now,
+        now + lease
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to claim a delivery task")
+}
+
+#[tracing::instrument(name = "recording a delivery attempt", skip(pool, task, outcome))]
+async fn record_attempt(
+    pool: &PgPool,
+    task: &DeliveryTask,
+    outcome: DeliveryOutcome,
+) -> Result<(), anyhow::Error> {
+    let n_attempts = task.n_attempts;
+    let (status, last_error, execute_after) = match outcome {
+        DeliveryOutcome::Dropped => {
+            sqlx::query!(
+                r#"
+                DELETE FROM issue_delivery_queue
+                WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+                "#,
+                task.newsletter_issue_id,
+                task.subscriber_email
+            )
+            .execute(pool)
+            .await
+            .context("failed to drop delivery task")?;
+            return Ok(());
+        }
+        DeliveryOutcome::Delivered => ("delivered", None, Utc::now()),
+        DeliveryOutcome::Retry(error) if n_attempts < MAX_DELIVERY_ATTEMPTS => {
+            let delay = chrono::Duration::from_std(retry_delay(n_attempts))
+                .context("retry delay is out of range")?;
+            ("pending", Some(error), Utc::now() + delay)
+        }
+        DeliveryOutcome::Retry(error) | DeliveryOutcome::DeadLetter(error) => {
+            tracing::error!("moving delivery task to the dead letter queue");
+            ("dead_lettered", Some(error), Utc::now())
+        }
+    };
+
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET status = $3, last_error = $4, execute_after = $5
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        status,
+        last_error,
+        execute_after
+    )
+    .execute(pool)
+    .await
+    .context("failed to record delivery attempt")?;
+    Ok(())
+}
+
+struct NewsletterIssue {
+    list_id: Uuid,
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+impl NewsletterIssue {
+    fn personalise(&self, fields: &MergeFields) -> Result<NewsletterIssue, TemplateError> {
+        Ok(NewsletterIssue {
+            list_id: self.list_id,
+            title: Template::parse(&self.title)?.render_text(fields),
+            text_content: Template::parse(&self.text_content)?.render_text(fields),
+            html_content: Template::parse(&self.html_content)?.render_html(fields),
+        })
+    }
+}
+
+#[tracing::instrument(name = "retrieving newsletter issue", skip(pool))]
+async fn get_issue(issue_id: Uuid, pool: &PgPool) -> Result<NewsletterIssue, anyhow::Error> {
+    sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT list_id, title, text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("failed to retrieve newsletter issue")
+}
+
+struct Recipient {
+    id: Uuid,
+    name: String,
+    attributes: HashMap<String, String>,
+}
+
+/// `None` unless the subscriber is still confirmed on the list, as they may
+/// have unsubscribed or been removed since the issue was queued.
+#[tracing::instrument(name = "retrieving recipient details", skip(pool))]
+async fn get_recipient(
+    email: &str,
+    list_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<Recipient>, anyhow::Error> {
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT subscriptions.id, subscriptions.name
+        FROM subscriptions
+        JOIN list_subscriptions ON list_subscriptions.subscriber_id = subscriptions.id
+        WHERE subscriptions.email = $1
+            AND list_subscriptions.list_id = $2
+            AND list_subscriptions.status = 'confirmed'
+        "#,
+        email,
+        list_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("failed to retrieve recipient")?;
+    let subscriber = match subscriber {
+        Some(subscriber) => subscriber,
+        None => return Ok(None),
+    };
+
+    let attributes = sqlx::query!(
+        r#"SELECT name, value FROM subscriber_attributes WHERE subscriber_id = $1"#,
+        subscriber.id
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to retrieve recipient attributes")?
+    .into_iter()
+    .map(|attribute| (attribute.name, attribute.value))
+    .collect();
+
+    Ok(Some(Recipient {
+        id: subscriber.id,
+        name: subscriber.name,
+        attributes,
+    }))
+}
@@ -1,7 +1,24 @@
+pub mod authentication;
+pub mod authorization;
+pub mod branding;
 pub mod common;
 pub mod configuration;
+pub mod confirmed_subscribers;
+pub mod delivery;
 pub mod domain;
 pub mod email_client;
+pub mod email_outbox;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod markdown;
+pub mod newsletter_scheduler;
+pub mod password_reset_token;
+pub mod rate_limiter;
 pub mod routes;
+pub mod session;
 pub mod startup;
+pub mod subscription_cleanup;
+pub mod subscription_token;
 pub mod telemetry;
+pub mod template;
+pub mod unsubscribe_token;
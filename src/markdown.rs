@@ -0,0 +1,200 @@
+use pulldown_cmark::{html, Event, Options, Parser, Tag};
+use uuid::Uuid;
+
+fn parser(markdown: &str) -> Parser<'_> {
+    Parser::new_ext(
+        markdown,
+        Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES,
+    )
+}
+
+pub fn render_html(markdown: &str) -> String {
+    let fields = MergeFieldMarkers::new(markdown);
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, parser(&fields.markdown));
+    fields.restore(ammonia::clean(&unsafe_html))
+}
+
+pub fn render_text(markdown: &str) -> String {
+    let fields = MergeFieldMarkers::new(markdown);
+    fields.restore(render_plain_text(&fields.markdown))
+}
+
+/// Merge fields such as `{{ unsubscribe_url }}` are swapped for plain
+/// alphanumeric markers while the markdown is rendered, otherwise one used as
+/// a link destination comes out percent-encoded and is never substituted.
+/// Only tags made of identifier characters are swapped, anything else is left
+/// for the template parser to reject.
+struct MergeFieldMarkers {
+    markdown: String,
+    prefix: String,
+    tags: Vec<String>,
+}
+
+impl MergeFieldMarkers {
+    fn new(markdown: &str) -> Self {
+        let prefix = format!("mergefield{}n", Uuid::new_v4().to_simple());
+        let mut protected = String::with_capacity(markdown.len());
+        let mut tags = Vec::new();
+        let mut rest = markdown;
+        while let Some(open) = rest.find("{{") {
+            let after_open = &rest[open + 2..];
+            let close = match after_open.find("}}") {
+                Some(close) => close,
+                None => break,
+            };
+            let expression = &after_open[..close];
+            let end = open + 2 + close + 2;
+            if expression
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == ' ')
+            {
+                protected.push_str(&rest[..open]);
+                protected.push_str(&format!("{}{}x", prefix, tags.len()));
+                tags.push(rest[open..end].to_string());
+            } else {
+                protected.push_str(&rest[..end]);
+            }
+            rest = &rest[end..];
+        }
+        protected.push_str(rest);
+        Self {
+            markdown: protected,
+            prefix,
+            tags,
+        }
+    }
+
+    fn restore(&self, mut rendered: String) -> String {
+        for (index, tag) in self.tags.iter().enumerate() {
+            rendered = rendered.replace(&format!("{}{}x", self.prefix, index), tag);
+        }
+        rendered
+    }
+}
+
+fn render_plain_text(markdown: &str) -> String {
+    let mut text = String::new();
+    let mut links: Vec<String> = Vec::new();
+    let mut lists: Vec<Option<u64>> = Vec::new();
+
+    for event in parser(markdown) {
+        match event {
+            Event::Start(Tag::List(first_number)) => {
+                end_line(&mut text);
+                lists.push(first_number);
+            }
+            Event::End(Tag::List(_)) => {
+                lists.pop();
+                if lists.is_empty() {
+                    text.push('\n');
+                }
+            }
+            Event::Start(Tag::Item) => {
+                text.push_str(&"  ".repeat(lists.len().saturating_sub(1)));
+                match lists.last_mut() {
+                    Some(Some(number)) => {
+                        text.push_str(&format!("{}. ", number));
+                        *number += 1;
+                    }
+                    _ => text.push_str("- "),
+                }
+            }
+            Event::End(Tag::Item) => end_line(&mut text),
+            Event::End(Tag::Paragraph) => {
+                end_line(&mut text);
+                if lists.is_empty() {
+                    text.push('\n');
+                }
+            }
+            Event::End(Tag::Heading(_)) | Event::End(Tag::CodeBlock(_)) => {
+                end_line(&mut text);
+                text.push('\n');
+            }
+            Event::End(Tag::TableRow) | Event::End(Tag::TableHead) => end_line(&mut text),
+            Event::End(Tag::TableCell) => text.push('\t'),
+            Event::End(Tag::Link(_, destination, _))
+            | Event::End(Tag::Image(_, destination, _)) => {
+                links.push(destination.to_string());
+                text.push_str(&format!(" [{}]", links.len()));
+            }
+            Event::Text(content) | Event::Code(content) => text.push_str(&content),
+            Event::SoftBreak | Event::HardBreak => text.push('\n'),
+            Event::Rule => text.push_str("---\n\n"),
+            _ => {}
+        }
+    }
+
+    let mut text = text.trim_end().to_string();
+    if !links.is_empty() {
+        text.push_str("\n\n");
+        for (index, link) in links.iter().enumerate() {
+            text.push_str(&format!("[{}] {}\n", index + 1, link));
+        }
+    }
+    text.trim_end().to_string()
+}
+
+fn end_line(text: &mut String) {
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_html, render_text};
+
+    #[test]
+    fn markdown_is_rendered_to_html() {
+        let html = render_html("# Issue 1\n\nSome **bold** news.");
+        assert_eq!(
+            html,
+            "<h1>Issue 1</h1>\n<p>Some <strong>bold</strong> news.</p>\n"
+        );
+    }
+
+    #[test]
+    fn unsafe_html_is_stripped() {
+        let html =
+            render_html("hello <script>alert('hi')</script> <a href=\"javascript:alert(1)\">x</a>");
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("javascript:"));
+    }
+
+    #[test]
+    fn links_become_footnotes_in_the_text_version() {
+        let text = render_text(
+            "Read [the post](https://example.com/post) and [the docs](https://example.com/docs).",
+        );
+        assert_eq!(
+            text,
+            "Read the post [1] and the docs [2].\n\n\
+            [1] https://example.com/post\n\
+            [2] https://example.com/docs"
+        );
+    }
+
+    #[test]
+    fn merge_fields_survive_in_link_destinations() {
+        let markdown = "Hi {{ name }}, [leave]({{unsubscribe_url}}) any time.";
+
+        assert_eq!(
+            render_html(markdown),
+            "<p>Hi {{ name }}, <a href=\"{{unsubscribe_url}}\" rel=\"noopener noreferrer\">leave</a> any time.</p>\n"
+        );
+        assert_eq!(
+            render_text(markdown),
+            "Hi {{ name }}, leave [1] any time.\n\n[1] {{unsubscribe_url}}"
+        );
+    }
+
+    #[test]
+    fn lists_and_headings_are_readable_in_the_text_version() {
+        let text = render_text("# News\n\n- one\n- two\n\n1. first\n2. second\n\nThe end.");
+        assert_eq!(
+            text,
+            "News\n\n- one\n- two\n\n1. first\n2. second\n\nThe end."
+        );
+    }
+}
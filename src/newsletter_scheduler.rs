@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use tracing::{field::display, Span};
+
+use crate::{
+    confirmed_subscribers::ConfirmedSubscriberReader, issue_delivery_worker::enqueue_delivery_tasks,
+};
+
+pub struct NewsletterScheduler {
+    pool: PgPool,
+}
+
+impl NewsletterScheduler {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn run_until_stopped(self) {
+        loop {
+            match try_release_issue(&self.pool).await {
+                Ok(ReleaseOutcome::NothingDue) => {
+                    actix_web::rt::time::sleep(Duration::from_secs(10)).await;
+                }
+                Ok(ReleaseOutcome::PageEnqueued) => {}
+                Err(_) => {
+                    actix_web::rt::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+}
+
+pub enum ReleaseOutcome {
+    PageEnqueued,
+    NothingDue,
+}
+
+const FAN_OUT_PAGE_SIZE: i64 = 500;
+
+// Issues are fanned out to the delivery queue one page of subscribers per
+// transaction, with the keyset cursor saved alongside the issue so that an
+// interrupted fan-out resumes from the last enqueued subscriber.
+#[tracing::instrument(
+    name = "enqueueing a page of a newsletter issue",
+    skip(pool),
+    fields(newsletter_issue_id = tracing::field::Empty),
+    err
+)]
+pub async fn try_release_issue(pool: &PgPool) -> Result<ReleaseOutcome, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to retrieve connection from pool")?;
+
+    let due_issue = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, list_id, status, delivery_cursor
+        FROM newsletter_issues
+        WHERE status = 'publishing'
+            OR (status = 'scheduled' AND scheduled_for <= $1)
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+        Utc::now()
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("failed to query issues awaiting delivery")?;
+
+    let due_issue = match due_issue {
+        Some(row) => row,
+        None => return Ok(ReleaseOutcome::NothingDue),
+    };
+    let issue_id = due_issue.newsletter_issue_id;
+    Span::current().record("newsletter_issue_id", &display(issue_id));
+
+    let mut subscribers = ConfirmedSubscriberReader::resume_after(
+        due_issue.list_id,
+        due_issue.delivery_cursor,
+        FAN_OUT_PAGE_SIZE,
+    );
+    let page = subscribers
+        .next_page(&mut transaction)
+        .await
+        .context("failed to read confirmed subscribers")?;
+
+    enqueue_delivery_tasks(issue_id, &page, &mut transaction)
+        .await
+        .context("failed to enqueue delivery tasks")?;
+
+    let status = if (page.len() as i64) < subscribers.page_size() {
+        "published"
+    } else {
+        "publishing"
+    };
+    let published_at = match due_issue.status.as_str() {
+        "scheduled" => Some(Utc::now()),
+        _ => None,
+    };
+
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = $2, delivery_cursor = $3, published_at = COALESCE($4, published_at)
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id,
+        status,
+        subscribers.cursor(),
+        published_at
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to save delivery cursor")?;
+
+    transaction
+        .commit()
+        .await
+        .context("failed to complete transaction")?;
+
+    Ok(ReleaseOutcome::PageEnqueued)
+}
@@ -0,0 +1,58 @@
+use hmac::{Hmac, Mac, NewMac};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sha2::Sha256;
+
+use crate::startup::HmacSecret;
+
+/// Sent by email to let a user choose a new password. Only its hash is stored.
+pub struct PasswordResetToken(String);
+
+impl PasswordResetToken {
+    pub fn generate() -> Self {
+        let mut rng = thread_rng();
+        let inner = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+            .map(char::from)
+            .take(32)
+            .collect();
+        Self(inner)
+    }
+
+    pub fn hash(&self, secret: &HmacSecret) -> String {
+        hash_password_reset_token(&self.0, secret)
+    }
+}
+
+impl AsRef<str> for PasswordResetToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+pub fn hash_password_reset_token(token: &str, secret: &HmacSecret) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.0.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(b"password_reset_token:");
+    mac.update(token.as_bytes());
+    base64::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash_password_reset_token, PasswordResetToken};
+    use crate::{startup::HmacSecret, subscription_token::hash_subscription_token};
+
+    #[test]
+    fn hashes_differ_from_subscription_token_hashes() {
+        let secret = HmacSecret("a-secret".to_string());
+        let token = PasswordResetToken::generate();
+
+        assert_eq!(
+            token.hash(&secret),
+            hash_password_reset_token(token.as_ref(), &secret)
+        );
+        assert_ne!(
+            token.hash(&secret),
+            hash_subscription_token(token.as_ref(), &secret)
+        );
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+pub struct RateLimiter {
+    bucket: Mutex<TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: u32) -> Self {
+        Self {
+            bucket: Mutex::new(TokenBucket::new(requests_per_second, Instant::now())),
+        }
+    }
+
+    pub async fn acquire(&self) {
+        loop {
+            let wait = self
+                .bucket
+                .lock()
+                .expect("rate limiter lock was poisoned")
+                .try_acquire(Instant::now());
+            match wait {
+                None => return,
+                Some(wait) => actix_web::rt::time::sleep(wait).await,
+            }
+        }
+    }
+
+    pub fn pause_for(&self, duration: Duration) {
+        self.bucket
+            .lock()
+            .expect("rate limiter lock was poisoned")
+            .pause_until(Instant::now() + duration);
+    }
+}
+
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_second: f64,
+    last_refill: Instant,
+    paused_until: Option<Instant>,
+}
+
+impl TokenBucket {
+    fn new(requests_per_second: u32, now: Instant) -> Self {
+        let refill_per_second = f64::from(requests_per_second.max(1));
+        Self {
+            capacity: refill_per_second,
+            tokens: refill_per_second,
+            refill_per_second,
+            last_refill: now,
+            paused_until: None,
+        }
+    }
+
+    fn try_acquire(&mut self, now: Instant) -> Option<Duration> {
+        if let Some(paused_until) = self.paused_until {
+            if paused_until > now {
+                return Some(paused_until - now);
+            }
+            self.paused_until = None;
+            self.last_refill = paused_until;
+        }
+
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.refill_per_second).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64(
+                (1.0 - self.tokens) / self.refill_per_second,
+            ))
+        }
+    }
+
+    fn pause_until(&mut self, until: Instant) {
+        let extends_pause = match self.paused_until {
+            Some(paused_until) => paused_until < until,
+            None => true,
+        };
+        if extends_pause {
+            self.paused_until = Some(until);
+        }
+        self.tokens = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use claim::{assert_none, assert_some};
+
+    use super::TokenBucket;
+
+    #[test]
+    fn a_full_bucket_allows_a_burst_up_to_its_capacity() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(3, now);
+
+        for _ in 0..3 {
+            assert_none!(bucket.try_acquire(now));
+        }
+        assert_some!(bucket.try_acquire(now));
+    }
+
+    #[test]
+    fn an_empty_bucket_refills_over_time() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(2, now);
+        bucket.try_acquire(now);
+        bucket.try_acquire(now);
+
+        let wait = bucket.try_acquire(now).unwrap();
+        assert_eq!(wait, Duration::from_millis(500));
+        assert_none!(bucket.try_acquire(now + wait));
+    }
+
+    #[test]
+    fn a_paused_bucket_waits_until_the_pause_ends() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(10, now);
+        bucket.pause_until(now + Duration::from_secs(5));
+
+        assert_eq!(
+            bucket.try_acquire(now + Duration::from_secs(1)),
+            Some(Duration::from_secs(4))
+        );
+        assert_some!(bucket.try_acquire(now + Duration::from_secs(5)));
+        assert_none!(bucket.try_acquire(now + Duration::from_secs(6)));
+    }
+}
@@ -0,0 +1,43 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use super::AdminError;
+use crate::{
+    authentication::authenticate_user, branding::render_page, configuration::BrandingSettings,
+    template::escape_html,
+};
+
+#[tracing::instrument(
+    name = "rendering the admin dashboard",
+    skip(pool, branding, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn admin_dashboard(
+    pool: web::Data<PgPool>,
+    branding: web::Data<BrandingSettings>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate_user(&request, &pool).await?;
+    let username = sqlx::query!("SELECT username FROM users WHERE user_id = $1", user_id)
+        .fetch_one(pool.as_ref())
+        .await
+        .context("failed to retrieve username")?
+        .username;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(render_page(
+            &branding,
+            "Admin dashboard",
+            &format!(
+                r#"<p>Welcome, {}!</p>
+<p><a href="/admin/password">Change password</a></p>
+<form action="/logout" method="post">
+<button type="submit" style="background-color: {}; color: #ffffff">Log out</button>
+</form>"#,
+                escape_html(&username),
+                escape_html(&branding.primary_color)
+            ),
+        )))
+}
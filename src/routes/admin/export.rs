@@ -0,0 +1,235 @@
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures::{channel::mpsc, SinkExt};
+use sqlx::PgPool;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use super::{AdminError, SubscriberFilters};
+use crate::{
+    authentication::{authenticate, Scope},
+    authorization::{authorize, Permission},
+};
+
+const FETCH_SIZE: i64 = 500;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ExportFormat {
+    Csv,
+    NdJson,
+}
+
+impl ExportFormat {
+    fn negotiate(request: &HttpRequest) -> Result<Self, AdminError> {
+        let accept = request
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .unwrap_or("*/*");
+        if accept.contains("text/csv") {
+            Ok(Self::Csv)
+        } else if accept.contains("application/x-ndjson") {
+            Ok(Self::NdJson)
+        } else if accept.contains("*/*") {
+            Ok(Self::Csv)
+        } else {
+            Err(AdminError::NotAcceptable(
+                "subscribers can be exported as text/csv or application/x-ndjson".to_string(),
+            ))
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::NdJson => "application/x-ndjson",
+        }
+    }
+
+    fn file_name(&self) -> &'static str {
+        match self {
+            Self::Csv => "subscribers.csv",
+            Self::NdJson => "subscribers.ndjson",
+        }
+    }
+}
+
+#[derive(serde::Serialize, sqlx::FromRow)]
+struct ExportedSubscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(
+    name = "exporting subscribers",
+    skip(filters, pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn export_subscribers(
+    filters: web::Query<SubscriberFilters>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate(&request, &pool, Scope::SubscribersRead).await?;
+    authorize(user_id, Permission::ReadSubscribers, &pool).await?;
+    filters.validate()?;
+    let format = ExportFormat::negotiate(&request)?;
+
+    // A small channel keeps at most a few chunks in memory: the export only
+    // fetches the next rows once the client has read the previous ones.
+    let (mut sender, receiver) = mpsc::channel(2);
+    let pool = pool.get_ref().clone();
+    actix_web::rt::spawn(
+        async move {
+            if let Err(e) = stream_subscribers(&pool, &filters, format, &mut sender).await {
+                tracing::error!(error.cause_chain = ?e, "failed to export subscribers");
+                let _ = sender.send(Err(e)).await;
+            }
+        }
+        .instrument(tracing::Span::current()),
+    );
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!(r#"attachment; filename="{}""#, format.file_name()),
+        ))
+        .streaming(receiver))
+}
+
+// Rows are read through a server side cursor so that neither Postgres nor
+// this process has to hold the whole export at once.
+async fn stream_subscribers(
+    pool: &PgPool,
+    filters: &SubscriberFilters,
+    format: ExportFormat,
+    sender: &mut mpsc::Sender<Result<web::Bytes, anyhow::Error>>,
+) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to retrieve connection from pool")?;
+    sqlx::query(
+        r#"
+        DECLARE subscriber_export NO SCROLL CURSOR FOR
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE ($1::TEXT IS NULL OR status = $1)
+            AND ($2::TIMESTAMPTZ IS NULL OR subscribed_at >= $2)
+            AND ($3::TIMESTAMPTZ IS NULL OR subscribed_at < $3)
+            AND ($4::TEXT IS NULL OR email ILIKE $4)
+        ORDER BY subscribed_at, id
+        "#,
+    )
+    .bind(filters.status())
+    .bind(filters.subscribed_after())
+    .bind(filters.subscribed_before())
+    .bind(filters.email_pattern())
+    .execute(&mut transaction)
+    .await
+    .context("failed to open the export cursor")?;
+
+    let mut with_header = true;
+    loop {
+        let subscribers: Vec<ExportedSubscriber> = sqlx::query_as(&format!(
+            "FETCH FORWARD {} FROM subscriber_export",
+            FETCH_SIZE
+        ))
+        .fetch_all(&mut transaction)
+        .await
+        .context("failed to fetch from the export cursor")?;
+
+        let chunk = encode_chunk(&subscribers, format, with_header)?;
+        with_header = false;
+        if !chunk.is_empty() && sender.send(Ok(chunk.into())).await.is_err() {
+            tracing::info!("client disconnected before the export completed");
+            break;
+        }
+        if (subscribers.len() as i64) < FETCH_SIZE {
+            break;
+        }
+    }
+
+    transaction
+        .rollback()
+        .await
+        .context("failed to close the export transaction")
+}
+
+fn encode_chunk(
+    subscribers: &[ExportedSubscriber],
+    format: ExportFormat,
+    with_header: bool,
+) -> Result<Vec<u8>, anyhow::Error> {
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(Vec::new());
+            if with_header {
+                writer.write_record(["id", "email", "name", "status", "subscribed_at"])?;
+            }
+            for subscriber in subscribers {
+                writer.serialize(subscriber)?;
+            }
+            writer.into_inner().context("failed to flush CSV writer")
+        }
+        ExportFormat::NdJson => {
+            let mut chunk = Vec::new();
+            for subscriber in subscribers {
+                serde_json::to_writer(&mut chunk, subscriber)?;
+                chunk.push(b'\n');
+            }
+            Ok(chunk)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    use super::{encode_chunk, ExportFormat, ExportedSubscriber};
+
+    fn subscriber() -> ExportedSubscriber {
+        ExportedSubscriber {
+            id: Uuid::nil(),
+            email: "alice@example.com".to_string(),
+            name: "Alice, Jr".to_string(),
+            status: "confirmed".to_string(),
+            subscribed_at: Utc.ymd(2021, 11, 1).and_hms(8, 0, 0),
+        }
+    }
+
+    #[test]
+    fn csv_chunks_quote_values_and_only_the_first_has_a_header() {
+        let first = encode_chunk(&[subscriber()], ExportFormat::Csv, true).unwrap();
+        let second = encode_chunk(&[subscriber()], ExportFormat::Csv, false).unwrap();
+
+        let row = "00000000-0000-0000-0000-000000000000,alice@example.com,\"Alice, Jr\",\
+            confirmed,2021-11-01T08:00:00Z\n";
+        assert_eq!(
+            String::from_utf8(first).unwrap(),
+            format!("id,email,name,status,subscribed_at\n{}", row)
+        );
+        assert_eq!(String::from_utf8(second).unwrap(), row);
+    }
+
+    #[test]
+    fn ndjson_chunks_have_one_object_per_line() {
+        let chunk =
+            encode_chunk(&[subscriber(), subscriber()], ExportFormat::NdJson, true).unwrap();
+        let chunk = String::from_utf8(chunk).unwrap();
+
+        let lines = chunk.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let parsed: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(parsed["email"], "alice@example.com");
+    }
+}
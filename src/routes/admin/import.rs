@@ -0,0 +1,379 @@
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+};
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::AdminError;
+use crate::{
+    authentication::{authenticate, Scope},
+    authorization::{authorize, Permission},
+    domain::NewSubscriber,
+    routes::subscriptions::{add_subscription_token, enqueue_confirmation_email},
+    startup::{ApplicationBaseUrl, HmacSecret, SubscriptionTokenExpiration},
+};
+
+const BATCH_SIZE: usize = 500;
+
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// Imported subscribers are confirmed straight away.
+    Confirmed,
+    /// Imported subscribers get a confirmation email, like a regular sign up.
+    DoubleOptIn,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ImportParams {
+    list_id: Uuid,
+    mode: ImportMode,
+}
+
+#[derive(serde::Serialize)]
+struct ImportReport {
+    imported: usize,
+    duplicates: usize,
+    errors: Vec<RowError>,
+}
+
+#[derive(serde::Serialize)]
+struct RowError {
+    line: u64,
+    field: Option<String>,
+    message: String,
+}
+
+struct ImportRow {
+    subscriber: NewSubscriber,
+    attributes: Vec<(String, String)>,
+}
+
+// Rows are committed one batch at a time. If a batch fails the earlier ones
+// stay imported, which is fine because importing the same file again only
+// counts the rows that made it as duplicates.
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(
+    name = "importing subscribers",
+    skip(params, body, pool, base_url, hmac_secret, token_expiration, request),
+    fields(
+        list_id = %params.list_id,
+        mode = ?params.mode,
+        username=tracing::field::Empty,
+        user_id=tracing::field::Empty
+    )
+)]
+pub async fn import_subscribers(
+    params: web::Query<ImportParams>,
+    body: web::Bytes,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    token_expiration: web::Data<SubscriptionTokenExpiration>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate(&request, &pool, Scope::SubscribersWrite).await?;
+    authorize(user_id, Permission::ManageSubscribers, &pool).await?;
+
+    let list_name = sqlx::query!("SELECT name FROM lists WHERE list_id = $1", params.list_id)
+        .fetch_optional(pool.as_ref())
+        .await
+        .context("failed to query mailing list")?
+        .ok_or_else(|| AdminError::ValidationError(format!("unknown list {}", params.list_id)))?
+        .name;
+
+    let (rows, mut errors, mut duplicates) = parse_rows(&body)?;
+
+    let mut imported = 0;
+    for batch in rows.chunks(BATCH_SIZE) {
+        let mut transaction = pool
+            .begin()
+            .await
+            .context("failed to retrieve connection from pool")?;
+        let new_rows = import_batch(batch, params.list_id, params.mode, &mut transaction)
+            .await
+            .context("failed to import a batch of subscribers")?;
+
+        if params.mode == ImportMode::DoubleOptIn {
+            for (subscriber_id, row) in &new_rows {
+                let token = add_subscription_token(
+                    *subscriber_id,
+                    params.list_id,
+                    &hmac_secret,
+                    token_expiration.0,
+                    &mut transaction,
+                )
+                .await
+                .context("failed to insert confirmation token")?;
+                enqueue_confirmation_email(
+                    NewSubscriber {
+                        email: row.subscriber.email.clone(),
+                        name: row.subscriber.name.clone(),
+                    },
+                    &list_name,
+                    &base_url,
+                    token,
+                    &mut transaction,
+                )
+                .await
+                .context("failed to queue confirmation email")?;
+            }
+        }
+
+        transaction
+            .commit()
+            .await
+            .context("failed to complete transaction")?;
+        imported += new_rows.len();
+        duplicates += batch.len() - new_rows.len();
+    }
+
+    errors.sort_by_key(|e| e.line);
+    Ok(HttpResponse::Ok().json(ImportReport {
+        imported,
+        duplicates,
+        errors,
+    }))
+}
+
+/// Splits the upload into valid rows, per-row errors and the number of rows
+/// repeating an email seen earlier in the file.
+fn parse_rows(body: &[u8]) -> Result<(Vec<ImportRow>, Vec<RowError>, usize), AdminError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(body);
+    let headers = reader
+        .headers()
+        .map_err(|e| AdminError::ValidationError(format!("invalid CSV header: {}", e)))?
+        .iter()
+        .map(attribute_name)
+        .collect::<Vec<_>>();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header == name)
+            .ok_or_else(|| AdminError::ValidationError(format!("missing `{}` column", name)))
+    };
+    let email_column = column("email")?;
+    let name_column = column("name")?;
+
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    let mut duplicates = 0;
+    let mut seen_emails = HashSet::new();
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                errors.push(RowError {
+                    line: e.position().map(|p| p.line()).unwrap_or_default(),
+                    field: None,
+                    message: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+        let field_error = |field: &str, message: String| RowError {
+            line,
+            field: Some(field.to_string()),
+            message,
+        };
+
+        let email = match record
+            .get(email_column)
+            .unwrap_or("")
+            .to_string()
+            .try_into()
+        {
+            Ok(email) => email,
+            Err(message) => {
+                errors.push(field_error("email", message));
+                continue;
+            }
+        };
+        let name = match record.get(name_column).unwrap_or("").to_string().try_into() {
+            Ok(name) => name,
+            Err(message) => {
+                errors.push(field_error("name", message));
+                continue;
+            }
+        };
+        let subscriber = NewSubscriber { email, name };
+        if !seen_emails.insert(subscriber.email.as_ref().to_string()) {
+            duplicates += 1;
+            continue;
+        }
+
+        let attributes = headers
+            .iter()
+            .zip(record.iter())
+            .enumerate()
+            .filter(|(i, (_, value))| *i != email_column && *i != name_column && !value.is_empty())
+            .map(|(_, (header, value))| (header.clone(), value.to_string()))
+            .collect();
+        rows.push(ImportRow {
+            subscriber,
+            attributes,
+        });
+    }
+
+    Ok((rows, errors, duplicates))
+}
+
+/// Turns a CSV header such as `First Name` into an attribute name usable from
+/// templates, `first_name`.
+fn attribute_name(header: &str) -> String {
+    header
+        .trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Imports one batch and returns the rows that were not on the list yet.
+async fn import_batch<'a>(
+    batch: &'a [ImportRow],
+    list_id: Uuid,
+    mode: ImportMode,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Vec<(Uuid, &'a ImportRow)>, sqlx::Error> {
+    let now = Utc::now();
+    let ids = batch.iter().map(|_| Uuid::new_v4()).collect::<Vec<_>>();
+    let emails = batch
+        .iter()
+        .map(|row| row.subscriber.email.as_ref().to_string())
+        .collect::<Vec<_>>();
+    let names = batch
+        .iter()
+        .map(|row| row.subscriber.name.as_ref().to_string())
+        .collect::<Vec<_>>();
+
+    let subscriber_ids = sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        SELECT id, email, name, $4, 'pending'
+        FROM UNNEST($1::UUID[], $2::TEXT[], $3::TEXT[]) AS rows(id, email, name)
+        ON CONFLICT (email) DO UPDATE SET email = EXCLUDED.email
+        RETURNING id, email
+        "#,
+        &ids,
+        &emails,
+        &names,
+        now
+    )
+    .fetch_all(&mut *transaction)
+    .await?
+    .into_iter()
+    .map(|r| (r.email, r.id))
+    .collect::<HashMap<_, _>>();
+    let subscriber_ids = emails
+        .iter()
+        .map(|email| subscriber_ids[email])
+        .collect::<Vec<_>>();
+
+    let status = match mode {
+        ImportMode::Confirmed => "confirmed",
+        ImportMode::DoubleOptIn => "pending",
+    };
+    let added = sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+        SELECT $1, subscriber_id, $3, $4
+        FROM UNNEST($2::UUID[]) AS rows(subscriber_id)
+        ON CONFLICT (list_id, subscriber_id) DO NOTHING
+        RETURNING subscriber_id
+        "#,
+        list_id,
+        &subscriber_ids,
+        status,
+        now
+    )
+    .fetch_all(&mut *transaction)
+    .await?
+    .into_iter()
+    .map(|r| r.subscriber_id)
+    .collect::<HashSet<_>>();
+
+    let new_rows = subscriber_ids
+        .into_iter()
+        .zip(batch)
+        .filter(|(subscriber_id, _)| added.contains(subscriber_id))
+        .collect::<Vec<_>>();
+    let new_ids = new_rows.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+
+    if mode == ImportMode::Confirmed {
+        sqlx::query!(
+            "UPDATE subscriptions SET status = 'confirmed' WHERE id = ANY($1)",
+            &new_ids
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+
+    let (mut attribute_owners, mut attribute_names, mut attribute_values) =
+        (Vec::new(), Vec::new(), Vec::new());
+    for (subscriber_id, row) in &new_rows {
+        for (name, value) in &row.attributes {
+            attribute_owners.push(*subscriber_id);
+            attribute_names.push(name.clone());
+            attribute_values.push(value.clone());
+        }
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_attributes (subscriber_id, name, value)
+        SELECT * FROM UNNEST($1::UUID[], $2::TEXT[], $3::TEXT[])
+        ON CONFLICT (subscriber_id, name) DO UPDATE SET value = EXCLUDED.value
+        "#,
+        &attribute_owners,
+        &attribute_names,
+        &attribute_values
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(new_rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{attribute_name, parse_rows};
+
+    #[test]
+    fn headers_become_attribute_names() {
+        assert_eq!(attribute_name(" First Name "), "first_name");
+        assert_eq!(attribute_name("Company"), "company");
+    }
+
+    #[test]
+    fn rows_are_validated_and_deduplicated() {
+        let csv = "email,name,City\n\
+            a@example.com,Alice,Paris\n\
+            not-an-email,Bob,\n\
+            a@example.com,Alice again,\n\
+            c@example.com,,Rome\n";
+
+        let (rows, errors, duplicates) = parse_rows(csv.as_bytes()).unwrap();
+
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].attributes, vec![("city".into(), "Paris".into())]);
+        assert_eq!(duplicates, 1);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].line, 3);
+        assert_eq!(errors[0].field.as_deref(), Some("email"));
+        assert_eq!(errors[1].line, 5);
+        assert_eq!(errors[1].field.as_deref(), Some("name"));
+    }
+
+    #[test]
+    fn uploads_without_an_email_column_are_rejected() {
+        assert!(parse_rows(b"name\nAlice\n").is_err());
+    }
+}
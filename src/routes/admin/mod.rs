@@ -0,0 +1,106 @@
+mod dashboard;
+mod export;
+mod import;
+mod password;
+mod stats;
+mod subscribers;
+mod tokens;
+
+pub use dashboard::*;
+pub use export::*;
+pub use import::*;
+pub use password::*;
+pub use stats::*;
+pub use subscribers::*;
+pub use tokens::*;
+
+use std::{fmt::Debug, time::Duration};
+
+use actix_http::{header::HeaderValue, StatusCode};
+use actix_web::{http::header, HttpResponse, ResponseError};
+
+use crate::{
+    authentication::AuthError, authorization::AuthorizationError, common::error_chain_fmt,
+};
+
+#[derive(thiserror::Error)]
+pub enum AdminError {
+    #[error("Authentication failed")]
+    AuthError(#[source] anyhow::Error),
+    #[error("Too many failed login attempts")]
+    TooManyAttempts(Duration),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    NotAcceptable(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl From<AuthError> for AdminError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidCredentials(e) => AdminError::AuthError(e),
+            AuthError::TooManyAttempts(retry_after) => AdminError::TooManyAttempts(retry_after),
+            e @ AuthError::MissingScope(_) => AdminError::Forbidden(e.to_string()),
+            AuthError::UnexpectedError(e) => AdminError::UnexpectedError(e),
+        }
+    }
+}
+
+impl From<AuthorizationError> for AdminError {
+    fn from(e: AuthorizationError) -> Self {
+        match e {
+            AuthorizationError::Forbidden(_) => AdminError::Forbidden(e.to_string()),
+            AuthorizationError::UnexpectedError(e) => AdminError::UnexpectedError(e),
+        }
+    }
+}
+
+impl Debug for AdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for AdminError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AdminError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            AdminError::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
+            AdminError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AdminError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            AdminError::NotFound(_) => StatusCode::NOT_FOUND,
+            AdminError::NotAcceptable(_) => StatusCode::NOT_ACCEPTABLE,
+            AdminError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            AdminError::AuthError(_) => {
+                let mut response = HttpResponse::new(self.status_code());
+                let header_value = HeaderValue::from_str(r#"Basic realm="admin""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, header_value);
+                response
+            }
+            AdminError::TooManyAttempts(retry_after) => HttpResponse::build(self.status_code())
+                .insert_header((header::RETRY_AFTER, retry_after.as_secs().to_string()))
+                .content_type("text/plain; charset=utf-8")
+                .body(self.to_string()),
+            AdminError::Forbidden(_)
+            | AdminError::ValidationError(_)
+            | AdminError::NotFound(_)
+            | AdminError::NotAcceptable(_) => HttpResponse::build(self.status_code())
+                .content_type("text/plain; charset=utf-8")
+                .body(self.to_string()),
+            AdminError::UnexpectedError(_) => HttpResponse::new(self.status_code()),
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use super::AdminError;
+use crate::{
+    authentication::{
+        authenticate_user, change_password, verify_credentials, AuthError, Credentials,
+    },
+    branding::render_page,
+    configuration::BrandingSettings,
+    domain::NewPassword,
+    session::{flash_cookie, flash_message, removal_cookie, SessionUser, FLASH_COOKIE},
+    startup::{ApplicationBaseUrl, HmacSecret},
+    template::escape_html,
+};
+
+#[derive(serde::Deserialize)]
+pub struct PasswordChange {
+    current_password: String,
+    new_password: String,
+    new_password_check: String,
+}
+
+#[tracing::instrument(
+    name = "rendering the change password form",
+    skip(pool, branding, hmac_secret, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn change_password_form(
+    pool: web::Data<PgPool>,
+    branding: web::Data<BrandingSettings>,
+    hmac_secret: web::Data<HmacSecret>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate_user(&request, &pool).await?;
+    let message = flash_message(&request, &hmac_secret)
+        .map(|message| format!(r#"<p role="alert"><i>{}</i></p>"#, escape_html(&message)))
+        .unwrap_or_default();
+
+    let mut response = HttpResponse::Ok();
+    if request.cookie(FLASH_COOKIE).is_some() {
+        response.del_cookie(&removal_cookie(FLASH_COOKIE));
+    }
+    Ok(response
+        .content_type("text/html; charset=utf-8")
+        .body(render_page(
+            &branding,
+            "Change password",
+            &format!(
+                r#"{}
+<form action="/admin/password" method="post">
+<p><label>Current password <input type="password" name="current_password" required></label></p>
+<p><label>New password <input type="password" name="new_password" required></label></p>
+<p><label>Confirm new password <input type="password" name="new_password_check" required></label></p>
+<button type="submit" style="background-color: {}; color: #ffffff">Change password</button>
+</form>
+<p><a href="/admin/dashboard">Back to the dashboard</a></p>"#,
+                message,
+                escape_html(&branding.primary_color)
+            ),
+        )))
+}
+
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(
+    name = "changing password",
+    skip(form, pool, hmac_secret, base_url, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn change_admin_password(
+    form: web::Form<PasswordChange>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate_user(&request, &pool).await?;
+    let PasswordChange {
+        current_password,
+        new_password,
+        new_password_check,
+    } = form.0;
+    let redirect_with = |message: &str| {
+        HttpResponse::SeeOther()
+            .insert_header((header::LOCATION, "/admin/password"))
+            .cookie(flash_cookie(message, &hmac_secret, base_url.is_https()))
+            .finish()
+    };
+
+    let new_password = match NewPassword::parse(new_password, &new_password_check) {
+        Ok(new_password) => new_password,
+        Err(message) => return Ok(redirect_with(&message)),
+    };
+
+    let username = sqlx::query!("SELECT username FROM users WHERE user_id = $1", user_id)
+        .fetch_one(pool.as_ref())
+        .await
+        .context("failed to retrieve username")?
+        .username;
+    let credentials = Credentials {
+        username,
+        password: current_password,
+    };
+    match verify_credentials(credentials, &request, &pool).await {
+        Ok(_) => {}
+        Err(AuthError::InvalidCredentials(_)) => {
+            return Ok(redirect_with("The current password is incorrect."))
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    // Other sessions may belong to whoever knew the old password, only the
+    // one making the change stays logged in.
+    let current_session = request
+        .extensions()
+        .get::<SessionUser>()
+        .map(|session| session.session_id);
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to retrieve connection from pool")?;
+    change_password(user_id, new_password, &mut transaction).await?;
+    sqlx::query!(
+        "DELETE FROM sessions WHERE user_id = $1 AND session_id IS DISTINCT FROM $2",
+        user_id,
+        current_session
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to delete the user's other sessions")?;
+    transaction
+        .commit()
+        .await
+        .context("failed to complete transaction")?;
+    Ok(redirect_with("Your password has been changed."))
+}
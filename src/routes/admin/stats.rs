@@ -0,0 +1,78 @@
+use std::collections::BTreeMap;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::AdminError;
+use crate::{
+    authentication::{authenticate, Scope},
+    authorization::{authorize, Permission},
+};
+
+#[derive(serde::Serialize)]
+struct ListStats {
+    list_id: Uuid,
+    name: String,
+    by_status: BTreeMap<String, i64>,
+}
+
+#[derive(serde::Serialize)]
+struct SubscriberStats {
+    by_status: BTreeMap<String, i64>,
+    lists: Vec<ListStats>,
+}
+
+/// How many subscribers there are, without revealing who they are.
+#[tracing::instrument(
+    name = "computing subscriber statistics",
+    skip(pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn subscriber_stats(
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate(&request, &pool, Scope::SubscribersRead).await?;
+    authorize(user_id, Permission::ReadSubscriberStats, &pool).await?;
+
+    let by_status =
+        sqlx::query!(r#"SELECT status, COUNT(*) AS "count!" FROM subscriptions GROUP BY status"#)
+            .fetch_all(pool.as_ref())
+            .await
+            .context("failed to count subscribers")?
+            .into_iter()
+            .map(|r| (r.status, r.count))
+            .collect();
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT lists.list_id, lists.name, list_subscriptions.status AS "status?",
+            COUNT(list_subscriptions.subscriber_id) AS "count!"
+        FROM lists
+        LEFT JOIN list_subscriptions ON list_subscriptions.list_id = lists.list_id
+        GROUP BY lists.list_id, lists.name, list_subscriptions.status
+        ORDER BY lists.name
+        "#
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("failed to count list subscriptions")?;
+    let mut lists: Vec<ListStats> = Vec::new();
+    for row in rows {
+        if lists.last().map(|list| list.list_id) != Some(row.list_id) {
+            lists.push(ListStats {
+                list_id: row.list_id,
+                name: row.name,
+                by_status: BTreeMap::new(),
+            });
+        }
+        // A list nobody subscribed to comes back once, without a status.
+        if let (Some(list), Some(status)) = (lists.last_mut(), row.status) {
+            list.by_status.insert(status, row.count);
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(SubscriberStats { by_status, lists }))
+}
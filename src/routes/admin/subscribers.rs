@@ -0,0 +1,419 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, SecondsFormat, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::AdminError;
+use crate::{
+    authentication::{authenticate, Scope},
+    authorization::{authorize, Permission},
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(serde::Deserialize)]
+pub struct SubscriberFilters {
+    status: Option<String>,
+    subscribed_after: Option<DateTime<Utc>>,
+    subscribed_before: Option<DateTime<Utc>>,
+    email: Option<String>,
+}
+
+impl SubscriberFilters {
+    pub fn validate(&self) -> Result<(), AdminError> {
+        match self.status.as_deref() {
+            None | Some("pending") | Some("confirmed") => Ok(()),
+            Some(status) => Err(AdminError::ValidationError(format!(
+                "unknown subscriber status {}",
+                status
+            ))),
+        }
+    }
+
+    pub fn status(&self) -> Option<&str> {
+        self.status.as_deref()
+    }
+
+    pub fn subscribed_after(&self) -> Option<DateTime<Utc>> {
+        self.subscribed_after
+    }
+
+    pub fn subscribed_before(&self) -> Option<DateTime<Utc>> {
+        self.subscribed_before
+    }
+
+    /// An `ILIKE` pattern matching emails that contain the requested substring.
+    pub fn email_pattern(&self) -> Option<String> {
+        self.email.as_ref().map(|email| {
+            let escaped = email
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            format!("%{}%", escaped)
+        })
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct PageParams {
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+/// Position of the last subscriber on a page, handed out to clients as an opaque string.
+#[derive(Debug)]
+struct Cursor {
+    subscribed_at: DateTime<Utc>,
+    id: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        base64::encode_config(
+            format!(
+                "{}|{}",
+                self.subscribed_at
+                    .to_rfc3339_opts(SecondsFormat::Micros, true),
+                self.id
+            ),
+            base64::URL_SAFE_NO_PAD,
+        )
+    }
+
+    fn decode(cursor: &str) -> Result<Self, anyhow::Error> {
+        let decoded = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+            .context("cursor is not valid base64")?;
+        let decoded = String::from_utf8(decoded).context("cursor is not valid UTF-8")?;
+        let (subscribed_at, id) = decoded
+            .split_once('|')
+            .context("cursor is missing a separator")?;
+        Ok(Self {
+            subscribed_at: DateTime::parse_from_rfc3339(subscribed_at)
+                .context("cursor has an invalid timestamp")?
+                .with_timezone(&Utc),
+            id: id.parse().context("cursor has an invalid id")?,
+        })
+    }
+}
+
+#[derive(serde::Serialize)]
+struct SubscriberSummary {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct SubscriberPage {
+    subscribers: Vec<SubscriberSummary>,
+    next_cursor: Option<String>,
+}
+
+#[tracing::instrument(
+    name = "listing subscribers",
+    skip(filters, page, pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn list_subscribers(
+    filters: web::Query<SubscriberFilters>,
+    page: web::Query<PageParams>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate(&request, &pool, Scope::SubscribersRead).await?;
+    authorize(user_id, Permission::ReadSubscribers, &pool).await?;
+    filters.validate()?;
+
+    let limit = page.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(AdminError::ValidationError(format!(
+            "limit must be between 1 and {}",
+            MAX_PAGE_SIZE
+        )));
+    }
+    let cursor = page
+        .cursor
+        .as_deref()
+        .map(Cursor::decode)
+        .transpose()
+        .map_err(|e| AdminError::ValidationError(e.to_string()))?;
+
+    // One extra row tells us whether there is a next page.
+    let mut subscribers = sqlx::query_as!(
+        SubscriberSummary,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE ($1::TEXT IS NULL OR status = $1)
+            AND ($2::TIMESTAMPTZ IS NULL OR subscribed_at >= $2)
+            AND ($3::TIMESTAMPTZ IS NULL OR subscribed_at < $3)
+            AND ($4::TEXT IS NULL OR email ILIKE $4)
+            AND ($5::TIMESTAMPTZ IS NULL OR (subscribed_at, id) > ($5, $6))
+        ORDER BY subscribed_at, id
+        LIMIT $7
+        "#,
+        filters.status(),
+        filters.subscribed_after(),
+        filters.subscribed_before(),
+        filters.email_pattern(),
+        cursor.as_ref().map(|c| c.subscribed_at),
+        cursor.as_ref().map(|c| c.id),
+        limit + 1
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("failed to query subscribers")?;
+
+    let next_cursor = if subscribers.len() as i64 > limit {
+        subscribers.truncate(limit as usize);
+        subscribers.last().map(|last| {
+            Cursor {
+                subscribed_at: last.subscribed_at,
+                id: last.id,
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+
+    Ok(HttpResponse::Ok().json(SubscriberPage {
+        subscribers,
+        next_cursor,
+    }))
+}
+
+#[derive(serde::Serialize)]
+struct ListMembership {
+    list_id: Uuid,
+    name: String,
+    status: String,
+}
+
+#[derive(serde::Serialize)]
+struct SubscriberDetails {
+    #[serde(flatten)]
+    subscriber: SubscriberSummary,
+    lists: Vec<ListMembership>,
+}
+
+#[tracing::instrument(
+    name = "fetching a subscriber",
+    skip(pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn get_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate(&request, &pool, Scope::SubscribersRead).await?;
+    authorize(user_id, Permission::ReadSubscribers, &pool).await?;
+
+    let subscriber = sqlx::query_as!(
+        SubscriberSummary,
+        "SELECT id, email, name, status, subscribed_at FROM subscriptions WHERE id = $1",
+        *subscriber_id
+    )
+    .fetch_optional(pool.as_ref())
+    .await
+    .context("failed to query subscriber")?
+    .ok_or_else(|| AdminError::NotFound(format!("no subscriber with id {}", subscriber_id)))?;
+
+    let lists = sqlx::query_as!(
+        ListMembership,
+        r#"
+        SELECT lists.list_id, lists.name, list_subscriptions.status
+        FROM list_subscriptions
+        JOIN lists ON lists.list_id = list_subscriptions.list_id
+        WHERE list_subscriptions.subscriber_id = $1
+        ORDER BY lists.name
+        "#,
+        *subscriber_id
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("failed to query list subscriptions")?;
+
+    Ok(HttpResponse::Ok().json(SubscriberDetails { subscriber, lists }))
+}
+
+#[derive(serde::Deserialize)]
+pub struct StatusUpdate {
+    status: String,
+    list_id: Option<Uuid>,
+}
+
+// The status is changed on the subscriber's lists, either all of them or the
+// one named in the request. The subscriber-wide status stays confirmed while
+// any list is confirmed and only goes back to pending on an explicit reset.
+#[tracing::instrument(
+    name = "changing a subscriber's status",
+    skip(body, pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn update_subscriber_status(
+    subscriber_id: web::Path<Uuid>,
+    body: web::Json<StatusUpdate>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate(&request, &pool, Scope::SubscribersWrite).await?;
+    authorize(user_id, Permission::ManageSubscribers, &pool).await?;
+    if !["pending", "confirmed", "unsubscribed"].contains(&body.status.as_str()) {
+        return Err(AdminError::ValidationError(format!(
+            "unknown subscription status {}",
+            body.status
+        )));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to retrieve connection from pool")?;
+
+    let updated_rows = sqlx::query!(
+        r#"
+        UPDATE list_subscriptions
+        SET status = $3
+        WHERE subscriber_id = $1 AND ($2::UUID IS NULL OR list_id = $2)
+        "#,
+        *subscriber_id,
+        body.list_id,
+        body.status
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to update list subscriptions")?
+    .rows_affected();
+    if body.list_id.is_some() && updated_rows == 0 {
+        return Err(AdminError::NotFound(format!(
+            "subscriber {} is not on the requested list",
+            subscriber_id
+        )));
+    }
+
+    let updated_subscribers = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = CASE
+            WHEN EXISTS (
+                SELECT 1 FROM list_subscriptions
+                WHERE subscriber_id = $1 AND status = 'confirmed'
+            ) THEN 'confirmed'
+            WHEN $2 = 'pending' THEN 'pending'
+            ELSE status
+        END
+        WHERE id = $1
+        "#,
+        *subscriber_id,
+        body.status
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to update subscriber status")?
+    .rows_affected();
+    if updated_subscribers == 0 {
+        return Err(AdminError::NotFound(format!(
+            "no subscriber with id {}",
+            subscriber_id
+        )));
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("failed to complete transaction")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(
+    name = "deleting a subscriber",
+    skip(pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn delete_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate(&request, &pool, Scope::SubscribersWrite).await?;
+    authorize(user_id, Permission::ManageSubscribers, &pool).await?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to retrieve connection from pool")?;
+
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = $1",
+        *subscriber_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to delete subscription tokens")?;
+    sqlx::query!(
+        "DELETE FROM list_subscriptions WHERE subscriber_id = $1",
+        *subscriber_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to delete list subscriptions")?;
+    sqlx::query!(
+        "DELETE FROM subscriber_attributes WHERE subscriber_id = $1",
+        *subscriber_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to delete subscriber attributes")?;
+    let deleted_rows = sqlx::query!("DELETE FROM subscriptions WHERE id = $1", *subscriber_id)
+        .execute(&mut transaction)
+        .await
+        .context("failed to delete subscriber")?
+        .rows_affected();
+    if deleted_rows == 0 {
+        return Err(AdminError::NotFound(format!(
+            "no subscriber with id {}",
+            subscriber_id
+        )));
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("failed to complete transaction")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use claim::assert_err;
+    use uuid::Uuid;
+
+    use super::Cursor;
+
+    #[test]
+    fn cursors_round_trip() {
+        let cursor = Cursor {
+            subscribed_at: Utc.ymd(2021, 11, 20).and_hms_micro(10, 30, 0, 123_456),
+            id: Uuid::new_v4(),
+        };
+
+        let decoded = Cursor::decode(&cursor.encode()).unwrap();
+
+        assert_eq!(decoded.subscribed_at, cursor.subscribed_at);
+        assert_eq!(decoded.id, cursor.id);
+    }
+
+    #[test]
+    fn garbage_cursors_are_rejected() {
+        assert_err!(Cursor::decode("not a cursor"));
+    }
+}
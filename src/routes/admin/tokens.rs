@@ -0,0 +1,173 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::AdminError;
+use crate::{
+    authentication::{authenticate_user, ApiToken, Scope},
+    startup::HmacSecret,
+};
+
+const DEFAULT_TOKEN_LIFETIME_DAYS: i64 = 30;
+const MAX_TOKEN_LIFETIME_DAYS: i64 = 365;
+
+#[derive(serde::Deserialize)]
+pub struct NewApiToken {
+    name: String,
+    scopes: Vec<Scope>,
+    expires_in_days: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+struct CreatedApiToken {
+    token_id: Uuid,
+    name: String,
+    scopes: Vec<Scope>,
+    expires_at: DateTime<Utc>,
+    token: String,
+}
+
+#[derive(serde::Serialize)]
+struct ApiTokenSummary {
+    token_id: Uuid,
+    name: String,
+    scopes: Vec<String>,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+// Tokens can only be managed with a login session or a password, so a leaked
+// token cannot be used to mint more of them.
+#[tracing::instrument(
+    name = "creating an API token",
+    skip(body, pool, hmac_secret, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn create_api_token(
+    body: web::Json<NewApiToken>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate_user(&request, &pool).await?;
+    let NewApiToken {
+        name,
+        mut scopes,
+        expires_in_days,
+    } = body.0;
+
+    let name = name.trim().to_string();
+    if name.is_empty() || name.len() > 100 {
+        return Err(AdminError::ValidationError(
+            "the token name must be between 1 and 100 characters long".to_string(),
+        ));
+    }
+    scopes.sort_by_key(|scope| scope.as_str());
+    scopes.dedup();
+    if scopes.is_empty() {
+        return Err(AdminError::ValidationError(
+            "a token needs at least one scope".to_string(),
+        ));
+    }
+    let lifetime = expires_in_days.unwrap_or(DEFAULT_TOKEN_LIFETIME_DAYS);
+    if !(1..=MAX_TOKEN_LIFETIME_DAYS).contains(&lifetime) {
+        return Err(AdminError::ValidationError(format!(
+            "tokens must expire within 1 to {} days",
+            MAX_TOKEN_LIFETIME_DAYS
+        )));
+    }
+
+    let token = ApiToken::generate();
+    let token_id = Uuid::new_v4();
+    let created_at = Utc::now();
+    let expires_at = created_at + chrono::Duration::days(lifetime);
+    sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (token_id, user_id, name, token_hash, scopes, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        token_id,
+        user_id,
+        name,
+        token.hash(&hmac_secret),
+        &scopes
+            .iter()
+            .map(|scope| scope.as_str().to_string())
+            .collect::<Vec<_>>(),
+        created_at,
+        expires_at
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("failed to store API token")?;
+
+    Ok(HttpResponse::Created().json(CreatedApiToken {
+        token_id,
+        name,
+        scopes,
+        expires_at,
+        token: token.as_ref().to_string(),
+    }))
+}
+
+#[tracing::instrument(
+    name = "listing API tokens",
+    skip(pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn list_api_tokens(
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate_user(&request, &pool).await?;
+
+    let tokens = sqlx::query_as!(
+        ApiTokenSummary,
+        r#"
+        SELECT token_id, name, scopes, created_at, expires_at, last_used_at
+        FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at
+        "#,
+        user_id
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("failed to query API tokens")?;
+
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[tracing::instrument(
+    name = "revoking an API token",
+    skip(pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn revoke_api_token(
+    token_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate_user(&request, &pool).await?;
+
+    let deleted_rows = sqlx::query!(
+        "DELETE FROM api_tokens WHERE token_id = $1 AND user_id = $2",
+        *token_id,
+        user_id
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("failed to revoke API token")?
+    .rows_affected();
+    if deleted_rows == 0 {
+        return Err(AdminError::NotFound(format!(
+            "no API token with id {}",
+            token_id
+        )));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
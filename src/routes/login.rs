@@ -0,0 +1,159 @@
+use std::time::Duration;
+
+use actix_http::StatusCode;
+use actix_web::{http::header, web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{verify_credentials, AuthError, Credentials},
+    branding::render_page,
+    common::error_chain_fmt,
+    configuration::BrandingSettings,
+    session::{
+        create_session, delete_session, flash_cookie, flash_message, removal_cookie,
+        session_cookie, session_id, FLASH_COOKIE, SESSION_COOKIE,
+    },
+    startup::{ApplicationBaseUrl, HmacSecret, SessionExpiration},
+    template::escape_html,
+};
+
+#[derive(thiserror::Error)]
+pub enum LoginError {
+    #[error("Too many failed login attempts, try again later.")]
+    TooManyAttempts(Duration),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for LoginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for LoginError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            LoginError::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
+            LoginError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            LoginError::TooManyAttempts(retry_after) => HttpResponse::TooManyRequests()
+                .insert_header((header::RETRY_AFTER, retry_after.as_secs().to_string()))
+                .content_type("text/plain; charset=utf-8")
+                .body(self.to_string()),
+            LoginError::UnexpectedError(_) => HttpResponse::new(self.status_code()),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct LoginData {
+    username: String,
+    password: String,
+}
+
+pub async fn login_form(
+    request: HttpRequest,
+    branding: web::Data<BrandingSettings>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    let error = flash_message(&request, &hmac_secret)
+        .map(|message| format!(r#"<p role="alert"><i>{}</i></p>"#, escape_html(&message)))
+        .unwrap_or_default();
+    let mut response = HttpResponse::Ok();
+    // The message is only shown once, a reload gets a clean form.
+    if request.cookie(FLASH_COOKIE).is_some() {
+        response.del_cookie(&removal_cookie(FLASH_COOKIE));
+    }
+    response
+        .content_type("text/html; charset=utf-8")
+        .body(render_page(
+            &branding,
+            "Log in",
+            &format!(
+                r#"{}
+<form action="/login" method="post">
+<p><label>Username <input type="text" name="username" required></label></p>
+<p><label>Password <input type="password" name="password" required></label></p>
+<button type="submit" style="background-color: {}; color: #ffffff">Log in</button>
+</form>
+<p><a href="/password/forgot">Forgot your password?</a></p>"#,
+                error,
+                escape_html(&branding.primary_color)
+            ),
+        ))
+}
+
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(
+    name = "logging in",
+    skip(form, request, pool, hmac_secret, base_url, session_expiration),
+    fields(username=%form.username, user_id=tracing::field::Empty)
+)]
+pub async fn login(
+    form: web::Form<LoginData>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    session_expiration: web::Data<SessionExpiration>,
+) -> Result<HttpResponse, LoginError> {
+    let LoginData { username, password } = form.0;
+    let credentials = Credentials { username, password };
+    let secure = base_url.is_https();
+
+    match verify_credentials(credentials, &request, &pool).await {
+        Ok(user_id) => {
+            tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+            let session_id = create_session(&pool, user_id, session_expiration.0).await?;
+            Ok(HttpResponse::SeeOther()
+                .insert_header((header::LOCATION, "/admin/dashboard"))
+                .cookie(session_cookie(session_id, &hmac_secret, secure))
+                .finish())
+        }
+        Err(AuthError::InvalidCredentials(e)) => {
+            tracing::warn!(error.cause_chain = ?e, "failed login attempt");
+            Ok(HttpResponse::SeeOther()
+                .insert_header((header::LOCATION, "/login"))
+                .cookie(flash_cookie("Authentication failed", &hmac_secret, secure))
+                .finish())
+        }
+        Err(AuthError::TooManyAttempts(retry_after)) => {
+            Err(LoginError::TooManyAttempts(retry_after))
+        }
+        Err(AuthError::UnexpectedError(e)) => Err(e.into()),
+        Err(e @ AuthError::MissingScope(_)) => Err(anyhow::anyhow!(e).into()),
+    }
+}
+
+#[tracing::instrument(name = "logging out", skip(request, pool, hmac_secret, base_url))]
+pub async fn log_out(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, LoginError> {
+    let session_id = request
+        .cookie(SESSION_COOKIE)
+        .and_then(|cookie| session_id(&cookie, &hmac_secret));
+    if let Some(session_id) = session_id {
+        delete_session(&pool, session_id)
+            .await
+            .context("failed to delete session")?;
+    }
+    let secure = base_url.is_https();
+    Ok(HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, "/login"))
+        .del_cookie(&removal_cookie(SESSION_COOKIE))
+        .cookie(flash_cookie(
+            "You have successfully logged out.",
+            &hmac_secret,
+            secure,
+        ))
+        .finish())
+}
@@ -1,7 +1,15 @@
+mod admin;
 mod health_check;
+mod login;
 mod newsletters;
+mod password_reset;
 mod subscriptions;
+mod unsubscribe;
 
+pub use admin::*;
 pub use health_check::*;
+pub use login::*;
 pub use newsletters::*;
+pub use password_reset::*;
 pub use subscriptions::*;
+pub use unsubscribe::*;
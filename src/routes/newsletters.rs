@@ -1,4 +1,4 @@
-use std::{convert::TryInto, fmt::Debug};
+use std::{convert::TryInto, fmt::Debug, time::Duration};
 
 use actix_http::{
     header::{HeaderMap, HeaderValue},
@@ -6,23 +6,45 @@ use actix_http::{
 };
 use actix_web::{http::header, web, HttpResponse, ResponseError};
 use anyhow::Context;
-use argon2::{Argon2, PasswordHash, PasswordVerifier};
-use sqlx::PgPool;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 use crate::{
-    common::{error_chain_fmt, spawn_blocking_with_tracing},
-    domain::SubscriberEmail,
-    email_client::EmailClient,
+    authentication::{authenticate, AuthError, Scope},
+    authorization::{authorize, AuthorizationError, Permission},
+    common::error_chain_fmt,
+    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    markdown,
+    startup::IdempotencyKeyExpiration,
+    template::Template,
 };
 
 #[derive(serde::Deserialize)]
 pub struct BodyData {
+    /// Defaults to the original newsletter list.
+    list_id: Option<Uuid>,
     title: String,
-    content: Content,
+    content: Option<Content>,
+    markdown: Option<String>,
+    send_at: Option<DateTime<Utc>>,
 }
 
-#[derive(serde::Deserialize)]
+impl BodyData {
+    fn rendered_content(&self) -> Result<Content, String> {
+        match (&self.content, &self.markdown) {
+            (Some(content), None) => Ok(content.clone()),
+            (None, Some(markdown)) => Ok(Content {
+                html: markdown::render_html(markdown),
+                text: markdown::render_text(markdown),
+            }),
+            (Some(_), Some(_)) => Err("provide either content or markdown, not both".to_string()),
+            (None, None) => Err("either content or markdown must be provided".to_string()),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
 pub struct Content {
     html: String,
     text: String,
@@ -32,10 +54,38 @@ pub struct Content {
 pub enum PublishError {
     #[error("Authentication failed")]
     AuthError(#[source] anyhow::Error),
+    #[error("Too many failed login attempts")]
+    TooManyAttempts(Duration),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("{0}")]
+    NotFound(String),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
 
+impl From<AuthError> for PublishError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidCredentials(e) => PublishError::AuthError(e),
+            AuthError::TooManyAttempts(retry_after) => PublishError::TooManyAttempts(retry_after),
+            e @ AuthError::MissingScope(_) => PublishError::Forbidden(e.to_string()),
+            AuthError::UnexpectedError(e) => PublishError::UnexpectedError(e),
+        }
+    }
+}
+
+impl From<AuthorizationError> for PublishError {
+    fn from(e: AuthorizationError) -> Self {
+        match e {
+            AuthorizationError::Forbidden(_) => PublishError::Forbidden(e.to_string()),
+            AuthorizationError::UnexpectedError(e) => PublishError::UnexpectedError(e),
+        }
+    }
+}
+
 impl Debug for PublishError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         error_chain_fmt(self, f)
@@ -53,6 +103,21 @@ impl ResponseError for PublishError {
                     .insert(header::WWW_AUTHENTICATE, header_value);
                 response
             }
+            PublishError::TooManyAttempts(retry_after) => {
+                HttpResponse::build(StatusCode::TOO_MANY_REQUESTS)
+                    .insert_header((header::RETRY_AFTER, retry_after.as_secs().to_string()))
+                    .content_type("text/plain; charset=utf-8")
+                    .body(self.to_string())
+            }
+            PublishError::Forbidden(_) => HttpResponse::build(StatusCode::FORBIDDEN)
+                .content_type("text/plain; charset=utf-8")
+                .body(self.to_string()),
+            PublishError::ValidationError(_) => HttpResponse::build(StatusCode::BAD_REQUEST)
+                .content_type("text/plain; charset=utf-8")
+                .body(self.to_string()),
+            PublishError::NotFound(_) => HttpResponse::build(StatusCode::NOT_FOUND)
+                .content_type("text/plain; charset=utf-8")
+                .body(self.to_string()),
             PublishError::UnexpectedError(_) => {
                 HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
             }
@@ -62,176 +127,401 @@ impl ResponseError for PublishError {
 
 #[tracing::instrument(
     name = "publishing a newsletter issue",
-    skip(body, pool, email_client, request),
+    skip(body, pool, idempotency_key_expiration, request),
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
 pub async fn publish_newsletter(
     body: web::Json<BodyData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    idempotency_key_expiration: web::Data<IdempotencyKeyExpiration>,
     request: web::HttpRequest,
 ) -> Result<HttpResponse, PublishError> {
-    let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
-
-    let user_id = validate_credentials(credentials, &pool).await?;
-    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
-
-    let confirmed_subscribers = get_confirmed_subscribers(&pool).await?;
-
-    for subscriber in confirmed_subscribers {
-        match subscriber {
-            Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await
-                    .with_context(|| {
-                        format!("failed to send newsletter issue to {}", subscriber.email)
-                    })?;
-            }
-            Err(error) => {
-                tracing::warn!(
-                    error.cause_chain = ?error,
-                    "skipping a confirmed subscriber as their email was found to be invalid"
-                )
+    let user_id = authenticate(&request, &pool, Scope::NewsletterPublish).await?;
+    authorize(user_id, Permission::PublishNewsletters, &pool).await?;
+
+    let content = body
+        .rendered_content()
+        .map_err(PublishError::ValidationError)?;
+    validate_templates(&body.title, &content)?;
+    let idempotency_key = idempotency_key(request.headers())?;
+
+    let mut transaction = match &idempotency_key {
+        Some(idempotency_key) => {
+            match try_processing(
+                &pool,
+                idempotency_key,
+                user_id,
+                idempotency_key_expiration.0,
+            )
+            .await?
+            {
+                NextAction::StartProcessing(transaction) => transaction,
+                NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
             }
         }
+        None => pool
+            .begin()
+            .await
+            .context("failed to retrieve connection from pool")?,
+    };
+
+    let list_id = find_list(body.list_id, &mut transaction)
+        .await
+        .context("failed to query mailing list")?
+        .ok_or_else(|| {
+            PublishError::ValidationError(match body.list_id {
+                Some(list_id) => format!("unknown list {}", list_id),
+                None => "the default list does not exist".to_string(),
+            })
+        })?;
+
+    // A `send_at` within the clock skew allowance is sent right away.
+    let send_at = body
+        .send_at
+        .map(validate_send_at)
+        .transpose()?
+        .filter(|send_at| *send_at > Utc::now());
+
+    let issue_id =
+        insert_newsletter_issue(list_id, &body.title, &content, send_at, &mut transaction)
+            .await
+            .context("failed to store newsletter issue details")?;
+
+    let response = HttpResponse::Accepted().json(PublishResponse {
+        newsletter_issue_id: issue_id,
+    });
+
+    match idempotency_key {
+        Some(idempotency_key) => {
+            let response = save_response(transaction, &idempotency_key, user_id, response).await?;
+            Ok(response)
+        }
+        None => {
+            transaction
+                .commit()
+                .await
+                .context("failed to complete transaction")?;
+            Ok(response)
+        }
     }
+}
 
-    Ok(HttpResponse::Ok().finish())
+fn validate_templates(title: &str, content: &Content) -> Result<(), PublishError> {
+    for (field, source) in [
+        ("title", title),
+        ("html", &content.html),
+        ("text", &content.text),
+    ] {
+        Template::parse(source).map_err(|e| {
+            PublishError::ValidationError(format!("invalid template in {}: {}", field, e))
+        })?;
+    }
+    Ok(())
 }
 
-#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
-async fn validate_credentials(
-    credentials: Credentials,
-    pool: &PgPool,
-) -> Result<uuid::Uuid, PublishError> {
-    let mut user_id = None;
-    let mut expected_password_hash = "$argon2id$v=19$m=15000,t=2,p=1$\
-    gZiV/M1gPc22ElAH/Jh1Hw$\
-    CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
-        .to_string();
+/// Clients' clocks are allowed to run a little behind ours.
+const SEND_AT_CLOCK_SKEW_SECS: i64 = 60;
 
-    if let Some((stored_user_id, stored_password_hash)) = get_stored_credentials(&credentials, pool)
-        .await
-        .map_err(PublishError::UnexpectedError)?
-    {
-        user_id = Some(stored_user_id);
-        expected_password_hash = stored_password_hash;
+fn validate_send_at(send_at: DateTime<Utc>) -> Result<DateTime<Utc>, PublishError> {
+    if send_at < Utc::now() - chrono::Duration::seconds(SEND_AT_CLOCK_SKEW_SECS) {
+        return Err(PublishError::ValidationError(format!(
+            "send_at {} is in the past",
+            send_at.to_rfc3339()
+        )));
     }
+    Ok(send_at)
+}
 
-    spawn_blocking_with_tracing(move || {
-        verify_password_hash(expected_password_hash, credentials.password)
-    })
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<IdempotencyKey>, PublishError> {
+    headers
+        .get("Idempotency-Key")
+        .map(|header_value| {
+            header_value
+                .to_str()
+                .map_err(|_| {
+                    PublishError::ValidationError(
+                        "The 'Idempotency-Key' header was not a valid UTF8 string".to_string(),
+                    )
+                })?
+                .to_owned()
+                .try_into()
+                .map_err(PublishError::ValidationError)
+        })
+        .transpose()
+}
+
+#[derive(serde::Serialize)]
+struct PublishResponse {
+    newsletter_issue_id: Uuid,
+}
+
+/// Without a `list_id`, the 'newsletter' list every subscriber belonged to
+/// before there were lists.
+#[tracing::instrument(name = "looking up mailing list", skip(transaction))]
+async fn find_list(
+    list_id: Option<Uuid>,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    sqlx::query!(
+        r#"
+        SELECT list_id FROM lists
+        WHERE list_id = $1 OR ($1 IS NULL AND name = 'newsletter')
+        "#,
+        list_id
+    )
+    .fetch_optional(transaction)
     .await
-    .context("failed to spawn blocking task")
-    .map_err(PublishError::UnexpectedError)?
-    .context("invalid password")
-    .map_err(PublishError::AuthError)?;
+    .map(|r| r.map(|v| v.list_id))
+}
 
-    user_id.ok_or_else(|| PublishError::AuthError(anyhow::anyhow!("unknown username")))
+#[tracing::instrument(
+    name = "saving newsletter issue to the database",
+    skip(title, content, transaction)
+)]
+async fn insert_newsletter_issue(
+    list_id: Uuid,
+    title: &str,
+    content: &Content,
+    send_at: Option<DateTime<Utc>>,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    let (status, published_at) = match send_at {
+        Some(_) => ("scheduled", None),
+        None => ("publishing", Some(Utc::now())),
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            list_id,
+            title,
+            text_content,
+            html_content,
+            status,
+            scheduled_for,
+            published_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+        newsletter_issue_id,
+        list_id,
+        title,
+        content.text,
+        content.html,
+        status,
+        send_at,
+        published_at
+    )
+    .execute(transaction)
+    .await?;
+    Ok(newsletter_issue_id)
+}
+
+#[derive(serde::Serialize)]
+struct ScheduledIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    send_at: DateTime<Utc>,
 }
 
 #[tracing::instrument(
-    name = "verify password hash",
-    skip(expected_password_hash, password_candidate)
+    name = "listing scheduled newsletter issues",
+    skip(pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
-fn verify_password_hash(
-    expected_password_hash: String,
-    password_candidate: String,
-) -> Result<(), PublishError> {
-    let expected_password_hash = PasswordHash::new(&expected_password_hash)
-        .context("failed to parse hash in PHC string format")
-        .map_err(PublishError::UnexpectedError)?;
-
-    Argon2::default()
-        .verify_password(password_candidate.as_bytes(), &expected_password_hash)
-        .context("invalid password")
-        .map_err(PublishError::AuthError)
-}
-
-#[tracing::instrument(name = "retrieving user from database", skip(pool, credentials))]
-async fn get_stored_credentials(
-    credentials: &Credentials,
-    pool: &sqlx::Pool<sqlx::Postgres>,
-) -> Result<Option<(Uuid, String)>, anyhow::Error> {
-    let row: Option<_> = sqlx::query!(
+pub async fn list_scheduled_newsletters(
+    pool: web::Data<PgPool>,
+    request: web::HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    let user_id = authenticate(&request, &pool, Scope::NewsletterPublish).await?;
+    authorize(user_id, Permission::PublishNewsletters, &pool).await?;
+
+    let scheduled_issues = sqlx::query_as!(
+        ScheduledIssue,
         r#"
-        SELECT user_id, password_hash
-        FROM users
-        WHERE username = $1
+        SELECT newsletter_issue_id, title, scheduled_for as "send_at!"
+        FROM newsletter_issues
+        WHERE status = 'scheduled'
+        ORDER BY scheduled_for
+        "#
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("failed to query scheduled issues")?;
+
+    Ok(HttpResponse::Ok().json(scheduled_issues))
+}
+
+#[derive(serde::Deserialize)]
+pub struct RescheduleData {
+    send_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(
+    name = "rescheduling a newsletter issue",
+    skip(body, pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn reschedule_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    body: web::Json<RescheduleData>,
+    pool: web::Data<PgPool>,
+    request: web::HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    let user_id = authenticate(&request, &pool, Scope::NewsletterPublish).await?;
+    authorize(user_id, Permission::PublishNewsletters, &pool).await?;
+    let send_at = validate_send_at(body.send_at)?;
+
+    let updated_rows = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET scheduled_for = $2
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
         "#,
-        credentials.username
+        *newsletter_issue_id,
+        send_at
     )
-    .fetch_optional(pool)
+    .execute(pool.as_ref())
     .await
-    .context("failed to perform query to retrieve stored credentials")?
-    .map(|row| (row.user_id, row.password_hash));
-    Ok(row)
-}
-
-struct Credentials {
-    username: String,
-    password: String,
-}
-
-fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
-    let header_value = headers
-        .get("Authorization")
-        .context("The 'Authorization' header was missing")?
-        .to_str()
-        .context("The 'Authorization' header was not a valid UTF8 string")?;
-    let base64encoded_segment = header_value
-        .strip_prefix("Basic ")
-        .context("The authorization scheme was not 'Basic'")?;
-    let decoded_bytes = base64::decode_config(base64encoded_segment, base64::STANDARD)
-        .context("failed to base64 decode credentials")?;
-    let decoded_credentials =
-        String::from_utf8(decoded_bytes).context("decoded credential string is not valid URF-8")?;
-
-    let mut credentials = decoded_credentials.splitn(2, ':');
-    let username = credentials
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("a username must be provided for basic auth"))?
-        .to_string();
-    let password = credentials
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("a password must be provided for basic auth"))?
-        .to_string();
-
-    Ok(Credentials { username, password })
-}
-
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
-}
-
-#[tracing::instrument(name = "getting confirmed subscribers", skip(pool))]
-async fn get_confirmed_subscribers(
-    pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let rows = sqlx::query!(
+    .context("failed to reschedule newsletter issue")?
+    .rows_affected();
+
+    if updated_rows == 0 {
+        return Err(PublishError::NotFound(format!(
+            "no scheduled issue with id {}",
+            newsletter_issue_id
+        )));
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(
+    name = "cancelling a scheduled newsletter issue",
+    skip(pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn cancel_scheduled_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: web::HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    let user_id = authenticate(&request, &pool, Scope::NewsletterPublish).await?;
+    authorize(user_id, Permission::PublishNewsletters, &pool).await?;
+
+    let updated_rows = sqlx::query!(
         r#"
-        SELECT email
-        FROM subscriptions
-        WHERE status = 'confirmed'
+        UPDATE newsletter_issues
+        SET status = 'cancelled'
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
         "#,
+        *newsletter_issue_id
     )
-    .fetch_all(pool)
-    .await?;
+    .execute(pool.as_ref())
+    .await
+    .context("failed to cancel newsletter issue")?
+    .rows_affected();
 
-    let rows = rows
-        .into_iter()
-        .map(|subscriber| match subscriber.email.try_into() {
-            Ok(email) => Ok(ConfirmedSubscriber { email }),
-            Err(error) => Err(anyhow::anyhow!(error)),
-        })
-        .collect();
+    if updated_rows == 0 {
+        return Err(PublishError::NotFound(format!(
+            "no scheduled issue with id {}",
+            newsletter_issue_id
+        )));
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(serde::Serialize)]
+struct DeadLetter {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_attempts: i16,
+    last_error: Option<String>,
+    failed_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(
+    name = "listing dead lettered deliveries",
+    skip(pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn list_dead_letters(
+    pool: web::Data<PgPool>,
+    request: web::HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    let user_id = authenticate(&request, &pool, Scope::NewsletterPublish).await?;
+    authorize(user_id, Permission::PublishNewsletters, &pool).await?;
+
+    let dead_letters = sqlx::query_as!(
+        DeadLetter,
+        r#"
+        SELECT
+            newsletter_issue_id,
+            subscriber_email,
+            n_attempts,
+            last_error,
+            execute_after as failed_at
+        FROM issue_delivery_queue
+        WHERE status = 'dead_lettered'
+        ORDER BY execute_after
+        "#
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("failed to query dead lettered deliveries")?;
+
+    Ok(HttpResponse::Ok().json(dead_letters))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ReplayData {
+    newsletter_issue_id: Uuid,
+    subscriber_email: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct ReplayResponse {
+    replayed: u64,
+}
+
+#[tracing::instrument(
+    name = "replaying dead lettered deliveries",
+    skip(body, pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn replay_dead_letters(
+    body: web::Json<ReplayData>,
+    pool: web::Data<PgPool>,
+    request: web::HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    let user_id = authenticate(&request, &pool, Scope::NewsletterPublish).await?;
+    authorize(user_id, Permission::PublishNewsletters, &pool).await?;
+
+    let replayed = sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET status = 'pending', n_attempts = 0, last_error = NULL, execute_after = $3
+        WHERE status = 'dead_lettered'
+            AND newsletter_issue_id = $1
+            AND ($2::TEXT IS NULL OR subscriber_email = $2)
+        "#,
+        body.newsletter_issue_id,
+        body.subscriber_email,
+        Utc::now()
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("failed to replay dead lettered deliveries")?
+    .rows_affected();
+
+    if replayed == 0 {
+        return Err(PublishError::NotFound(format!(
+            "no dead lettered deliveries for issue {}",
+            body.newsletter_issue_id
+        )));
+    }
 
-    Ok(rows)
+    Ok(HttpResponse::Ok().json(ReplayResponse { replayed }))
 }
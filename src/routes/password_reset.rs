@@ -0,0 +1,338 @@
+use std::convert::TryInto;
+
+use actix_http::StatusCode;
+use actix_web::{http::header, web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    authentication::change_password,
+    branding::render_page,
+    common::error_chain_fmt,
+    configuration::BrandingSettings,
+    domain::{NewPassword, SubscriberEmail},
+    email_outbox::{enqueue_email, OutgoingEmail},
+    password_reset_token::{hash_password_reset_token, PasswordResetToken},
+    session::{flash_cookie, flash_message, removal_cookie, FLASH_COOKIE},
+    startup::{ApplicationBaseUrl, HmacSecret, PasswordResetTokenExpiration},
+    template::escape_html,
+};
+
+#[derive(thiserror::Error)]
+pub enum PasswordResetError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PasswordResetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PasswordResetError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PasswordResetError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ForgotPasswordData {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResetPasswordParams {
+    token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResetPasswordData {
+    token: String,
+    new_password: String,
+    new_password_check: String,
+}
+
+pub async fn forgot_password_form(
+    request: HttpRequest,
+    branding: web::Data<BrandingSettings>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    let message = flash_message(&request, &hmac_secret)
+        .map(|message| format!(r#"<p role="alert"><i>{}</i></p>"#, escape_html(&message)))
+        .unwrap_or_default();
+    let mut response = HttpResponse::Ok();
+    if request.cookie(FLASH_COOKIE).is_some() {
+        response.del_cookie(&removal_cookie(FLASH_COOKIE));
+    }
+    response
+        .content_type("text/html; charset=utf-8")
+        .body(render_page(
+            &branding,
+            "Forgot your password?",
+            &format!(
+                r#"{}
+<p>Enter the email address of your account and we will send you a link to choose a new password.</p>
+<form action="/password/forgot" method="post">
+<p><label>Email <input type="email" name="email" required></label></p>
+<button type="submit" style="background-color: {}; color: #ffffff">Send reset link</button>
+</form>"#,
+                message,
+                escape_html(&branding.primary_color)
+            ),
+        ))
+}
+
+// The reset email goes through the outbox in the same transaction as its
+// token, so known and unknown addresses get the same answer in about the same
+// time, and a failing email provider does not tell them apart either.
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(
+    name = "requesting a password reset",
+    skip(form, pool, base_url, hmac_secret, token_expiration),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn forgot_password(
+    form: web::Form<ForgotPasswordData>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    token_expiration: web::Data<PasswordResetTokenExpiration>,
+) -> Result<HttpResponse, PasswordResetError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to retrieve connection from pool")?;
+    let user = sqlx::query!(
+        "SELECT user_id, email FROM users WHERE lower(email) = lower($1)",
+        form.email.trim()
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("failed to look up user by email")?;
+
+    if let Some(user) = user {
+        tracing::Span::current().record("user_id", &tracing::field::display(&user.user_id));
+        let recipient: SubscriberEmail = user
+            .email
+            .context("a user matched by email has no email")?
+            .try_into()
+            .map_err(anyhow::Error::msg)
+            .context("the stored user email is invalid")?;
+
+        let token = PasswordResetToken::generate();
+        let created_at = Utc::now();
+        let expires_at = created_at
+            + chrono::Duration::from_std(token_expiration.0)
+                .context("password reset token expiration is out of range")?;
+        sqlx::query!(
+            r#"
+            INSERT INTO password_reset_tokens (token_hash, user_id, created_at, expires_at)
+            VALUES ($1, $2, $3, $4)
+            "#,
+            token.hash(&hmac_secret),
+            user.user_id,
+            created_at,
+            expires_at
+        )
+        .execute(&mut transaction)
+        .await
+        .context("failed to store password reset token")?;
+
+        let url = format!("{}/password/reset?token={}", base_url.0, token.as_ref());
+        enqueue_email(
+            OutgoingEmail {
+                recipient: &recipient,
+                subject: "Reset your password",
+                html_body: &format!(
+                    "Click <a href=\"{}\">here</a> to choose a new password. \
+                    The link can only be used once and expires in {} minutes.",
+                    url,
+                    token_expiration.0.as_secs() / 60
+                ),
+                text_body: &format!(
+                    "Visit {} to choose a new password.\n\
+                    The link can only be used once and expires in {} minutes.",
+                    url,
+                    token_expiration.0.as_secs() / 60
+                ),
+            },
+            &mut transaction,
+        )
+        .await
+        .context("failed to queue password reset email")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("failed to complete transaction")?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, "/login"))
+        .cookie(flash_cookie(
+            "If an account uses that address, a reset link is on its way.",
+            &hmac_secret,
+            base_url.is_https(),
+        ))
+        .finish())
+}
+
+#[tracing::instrument(
+    name = "rendering the reset password form",
+    skip(params, request, pool, branding, hmac_secret)
+)]
+pub async fn reset_password_form(
+    params: web::Query<ResetPasswordParams>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    branding: web::Data<BrandingSettings>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, PasswordResetError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to retrieve connection from pool")?;
+    let user_id = usable_reset_token(&params.token, &hmac_secret, &mut transaction).await?;
+    transaction
+        .rollback()
+        .await
+        .context("failed to complete transaction")?;
+    if user_id.is_none() {
+        return Ok(invalid_link_page(&branding));
+    }
+
+    let message = flash_message(&request, &hmac_secret)
+        .map(|message| format!(r#"<p role="alert"><i>{}</i></p>"#, escape_html(&message)))
+        .unwrap_or_default();
+    let mut response = HttpResponse::Ok();
+    if request.cookie(FLASH_COOKIE).is_some() {
+        response.del_cookie(&removal_cookie(FLASH_COOKIE));
+    }
+    Ok(response
+        .content_type("text/html; charset=utf-8")
+        .body(render_page(
+            &branding,
+            "Choose a new password",
+            &format!(
+                r#"{}
+<form action="/password/reset" method="post">
+<input type="hidden" name="token" value="{}">
+<p><label>New password <input type="password" name="new_password" required></label></p>
+<p><label>Confirm new password <input type="password" name="new_password_check" required></label></p>
+<button type="submit" style="background-color: {}; color: #ffffff">Reset password</button>
+</form>"#,
+                message,
+                escape_html(&params.token),
+                escape_html(&branding.primary_color)
+            ),
+        )))
+}
+
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(
+    name = "resetting a password",
+    skip(form, pool, branding, base_url, hmac_secret),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn reset_password(
+    form: web::Form<ResetPasswordData>,
+    pool: web::Data<PgPool>,
+    branding: web::Data<BrandingSettings>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, PasswordResetError> {
+    let ResetPasswordData {
+        token,
+        new_password,
+        new_password_check,
+    } = form.0;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to retrieve connection from pool")?;
+    let user_id = match usable_reset_token(&token, &hmac_secret, &mut transaction).await? {
+        Some(user_id) => user_id,
+        None => return Ok(invalid_link_page(&branding)),
+    };
+    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+
+    let new_password = match NewPassword::parse(new_password, &new_password_check) {
+        Ok(new_password) => new_password,
+        // Usable tokens are alphanumeric, they can go in the URL as they are.
+        Err(message) => {
+            return Ok(HttpResponse::SeeOther()
+                .insert_header((header::LOCATION, format!("/password/reset?token={}", token)))
+                .cookie(flash_cookie(&message, &hmac_secret, base_url.is_https()))
+                .finish())
+        }
+    };
+
+    change_password(user_id, new_password, &mut transaction).await?;
+    // Every outstanding link is spent and every session ends, whoever holds them.
+    sqlx::query!(
+        r#"
+        UPDATE password_reset_tokens SET used_at = $2
+        WHERE user_id = $1 AND used_at IS NULL
+        "#,
+        user_id,
+        Utc::now()
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to mark password reset tokens as used")?;
+    sqlx::query!("DELETE FROM sessions WHERE user_id = $1", user_id)
+        .execute(&mut transaction)
+        .await
+        .context("failed to delete the user's sessions")?;
+    transaction
+        .commit()
+        .await
+        .context("failed to complete transaction")?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, "/login"))
+        .cookie(flash_cookie(
+            "Your password has been reset, you can now log in.",
+            &hmac_secret,
+            base_url.is_https(),
+        ))
+        .finish())
+}
+
+/// The user a reset token belongs to, as long as it has not been used and has
+/// not expired. The token row stays locked until the transaction ends.
+async fn usable_reset_token(
+    token: &str,
+    secret: &HmacSecret,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let user_id = sqlx::query!(
+        r#"
+        SELECT user_id FROM password_reset_tokens
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > $2
+        FOR UPDATE
+        "#,
+        hash_password_reset_token(token, secret),
+        Utc::now()
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("failed to look up password reset token")?
+    .map(|r| r.user_id);
+    Ok(user_id)
+}
+
+fn invalid_link_page(branding: &BrandingSettings) -> HttpResponse {
+    HttpResponse::BadRequest()
+        .content_type("text/html; charset=utf-8")
+        .body(render_page(
+            branding,
+            "Invalid reset link",
+            r#"<p>This reset link is invalid, has already been used or has expired.</p>
+<p><a href="/password/forgot">Request a new one</a></p>"#,
+        ))
+}
@@ -1,46 +1,99 @@
 use std::{
     convert::TryInto,
     fmt::{Debug, Display},
+    time::Duration,
 };
 
 use actix_http::StatusCode;
 use anyhow::Context;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 
-use actix_web::{web, HttpResponse, ResponseError};
-use rand::{distributions::Alphanumeric, thread_rng, Rng};
-use sqlx::{PgPool, Postgres, Transaction};
+use actix_web::{
+    dev::Payload, http::header, web, FromRequest, HttpMessage, HttpRequest, HttpResponse,
+    ResponseError,
+};
+use futures::future::LocalBoxFuture;
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 use crate::{
-    common::error_chain_fmt, domain::NewSubscriber, email_client::EmailClient,
-    startup::ApplicationBaseUrl,
+    branding::render_page,
+    common::error_chain_fmt,
+    configuration::BrandingSettings,
+    domain::NewSubscriber,
+    email_outbox::{enqueue_email, OutgoingEmail},
+    startup::{ApplicationBaseUrl, HmacSecret, SubscriptionTokenExpiration},
+    subscription_token::{hash_subscription_token, SubscriptionToken},
+    template::escape_html,
 };
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
     name: String,
     email: String,
+    /// Defaults to the original newsletter list.
+    list_id: Option<Uuid>,
 }
 
 impl TryInto<NewSubscriber> for FormData {
-    type Error = String;
+    type Error = SubscribeError;
 
     fn try_into(self) -> Result<NewSubscriber, Self::Error> {
-        let email = self.email.try_into()?;
-        let name = self.name.try_into()?;
+        let email = self
+            .email
+            .try_into()
+            .map_err(|message| SubscribeError::ValidationError {
+                field: "email",
+                message,
+            })?;
+        let name = self
+            .name
+            .try_into()
+            .map_err(|message| SubscribeError::ValidationError {
+                field: "name",
+                message,
+            })?;
         Ok(NewSubscriber { email, name })
     }
 }
 
+/// The subscription payload, read as JSON when the request says so and as a
+/// urlencoded form otherwise.
+pub struct SubscriptionBody(FormData);
+
+impl FromRequest for SubscriptionBody {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        if req.content_type() == "application/json" {
+            let body = web::Json::<FormData>::from_request(req, payload);
+            Box::pin(async move { Ok(Self(body.await?.into_inner())) })
+        } else {
+            let body = web::Form::<FormData>::from_request(req, payload);
+            Box::pin(async move { Ok(Self(body.await?.into_inner())) })
+        }
+    }
+}
+
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
-    #[error("{0}")]
-    ValidationError(String),
+    #[error("{field}: {message}")]
+    ValidationError {
+        field: &'static str,
+        message: String,
+    },
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
 
+#[derive(serde::Serialize)]
+struct ValidationErrorResponse<'a> {
+    field: &'a str,
+    message: &'a str,
+}
+
 impl Debug for SubscribeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         error_chain_fmt(self, f)
@@ -50,10 +103,22 @@ impl Debug for SubscribeError {
 impl ResponseError for SubscribeError {
     fn status_code(&self) -> actix_http::StatusCode {
         match self {
-            SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::ValidationError { .. } => StatusCode::BAD_REQUEST,
             SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            SubscribeError::ValidationError { field, message } => {
+                HttpResponse::build(self.status_code())
+                    .json(ValidationErrorResponse { field, message })
+            }
+            SubscribeError::UnexpectedError(_) => HttpResponse::build(self.status_code())
+                .content_type("text/plain; charset=utf-8")
+                .body(self.to_string()),
+        }
+    }
 }
 
 pub struct StoreTokenError(sqlx::Error);
@@ -82,41 +147,73 @@ impl std::error::Error for StoreTokenError {
 #[allow(clippy::async_yields_async)]
 #[tracing::instrument(
     name="Adding a new subscriber",
-    skip(form, pool, email_client, base_url),
+    skip(body, pool, base_url, hmac_secret, token_expiration),
     fields(
-        user_email = %form.email,
-        user_name = %form.name
+        user_email = %body.0.email,
+        user_name = %body.0.name,
+        list_id = ?body.0.list_id
     )
 )]
 pub async fn subscribe(
-    form: web::Form<FormData>,
+    body: SubscriptionBody,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    token_expiration: web::Data<SubscriptionTokenExpiration>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let requested_list_id = body.0.list_id;
+    let new_subscriber = body.0.try_into()?;
 
     let mut transaction = pool
         .begin()
         .await
         .context("failed to retrieve connection from pool")?;
 
+    let (list_id, list_name) = get_list(requested_list_id, &mut transaction)
+        .await
+        .context("failed to query mailing list")?
+        .ok_or_else(|| SubscribeError::ValidationError {
+            field: "list_id",
+            message: match requested_list_id {
+                Some(list_id) => format!("unknown list {}", list_id),
+                None => "the default list does not exist".to_string(),
+            },
+        })?;
+
     let user_id = insert_new_user(&new_subscriber, &mut transaction)
         .await
         .context("failed to insert new user")?;
 
-    let confirmation_token = add_subscription_token(user_id, &mut transaction)
+    let status = add_list_subscription(user_id, list_id, &mut transaction)
         .await
-        .context("failed to insert confirmation token")?;
+        .context("failed to subscribe user to list")?;
+    if status == "confirmed" {
+        transaction
+            .commit()
+            .await
+            .context("failed to complete transaction")?;
+        return Ok(HttpResponse::Ok().finish());
+    }
 
-    send_confirmation_email(
-        &email_client,
+    let confirmation_token = add_subscription_token(
+        user_id,
+        list_id,
+        &hmac_secret,
+        token_expiration.0,
+        &mut transaction,
+    )
+    .await
+    .context("failed to insert confirmation token")?;
+
+    enqueue_confirmation_email(
         new_subscriber,
+        &list_name,
         base_url.as_ref(),
         confirmation_token,
+        &mut transaction,
     )
     .await
-    .context("failed to send confirmaiton email")?;
+    .context("failed to queue confirmation email")?;
 
     transaction
         .commit()
@@ -126,6 +223,27 @@ pub async fn subscribe(
     Ok(HttpResponse::Ok().finish())
 }
 
+/// Without a `list_id`, the 'newsletter' list every subscriber belonged to
+/// before there were lists.
+#[tracing::instrument(name = "looking up mailing list", skip(transaction))]
+async fn get_list(
+    list_id: Option<Uuid>,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Option<(Uuid, String)>, sqlx::Error> {
+    sqlx::query!(
+        r#"
+        SELECT list_id, name FROM lists
+        WHERE list_id = $1 OR ($1 IS NULL AND name = 'newsletter')
+        "#,
+        list_id
+    )
+    .fetch_optional(transaction)
+    .await
+    .map(|r| r.map(|v| (v.list_id, v.name)))
+}
+
+// A subscriber is shared by every list they join, so signing up for a second
+// list reuses the row created by the first.
 #[tracing::instrument(
     name = "saving new subscriber to the database",
     skip(subscriber, transaction)
@@ -134,35 +252,85 @@ async fn insert_new_user(
     subscriber: &NewSubscriber,
     transaction: &mut Transaction<'_, Postgres>,
 ) -> Result<Uuid, sqlx::Error> {
-    let uuid = Uuid::new_v4();
     sqlx::query!(
         r#"
         INSERT INTO subscriptions (id, email, name, subscribed_at, status)
         VALUES ($1, $2, $3, $4, 'pending')
+        ON CONFLICT (email) DO UPDATE SET email = EXCLUDED.email
+        RETURNING id
         "#,
-        uuid,
+        Uuid::new_v4(),
         subscriber.email.as_ref(),
         subscriber.name.as_ref(),
         Utc::now()
     )
-    .execute(transaction)
-    .await?;
-    Ok(uuid)
+    .fetch_one(transaction)
+    .await
+    .map(|r| r.id)
+}
+
+// Subscribing again is not an error: pending subscriptions get a fresh
+// confirmation email, confirmed ones are left alone and unsubscribed ones go
+// back to pending so that they have to be confirmed again.
+#[tracing::instrument(name = "subscribing user to a list", skip(transaction))]
+async fn add_list_subscription(
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<String, sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+        VALUES ($1, $2, 'pending', $3)
+        ON CONFLICT (list_id, subscriber_id) DO UPDATE
+        SET status = CASE
+            WHEN list_subscriptions.status = 'confirmed' THEN 'confirmed'
+            ELSE 'pending'
+        END
+        RETURNING status
+        "#,
+        list_id,
+        subscriber_id,
+        Utc::now()
+    )
+    .fetch_one(transaction)
+    .await
+    .map(|r| r.status)
 }
 
 #[tracing::instrument(
     name = "adding subscription token to the database",
-    skip(subscriber_id, transaction)
+    skip(subscriber_id, list_id, hmac_secret, expiration, transaction)
 )]
-async fn add_subscription_token(
+pub(crate) async fn add_subscription_token(
     subscriber_id: Uuid,
+    list_id: Uuid,
+    hmac_secret: &HmacSecret,
+    expiration: Duration,
     transaction: &mut Transaction<'_, Postgres>,
 ) -> Result<SubscriptionToken, StoreTokenError> {
-    let token = generate_subscription_token();
+    let token = SubscriptionToken::generate();
+    let created_at = Utc::now();
+    let expires_at = chrono::Duration::from_std(expiration)
+        .ok()
+        .and_then(|expiration| created_at.checked_add_signed(expiration))
+        .unwrap_or(chrono::MAX_DATETIME);
     sqlx::query!(
-        "INSERT INTO subscription_tokens (subscription_token, subscriber_id) VALUES ($1, $2)",
-        token.0,
-        subscriber_id
+        r#"
+        INSERT INTO subscription_tokens (
+            subscription_token_hash,
+            subscriber_id,
+            list_id,
+            created_at,
+            expires_at
+        )
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        token.hash(hmac_secret),
+        subscriber_id,
+        list_id,
+        created_at,
+        expires_at
     )
     .execute(transaction)
     .await
@@ -171,27 +339,38 @@ async fn add_subscription_token(
 }
 
 #[tracing::instrument(
-    name = "send a confirmation email to the subscriber",
-    skip(email_client, subscriber, base_url, confirmation_token)
+    name = "queueing a confirmation email to the subscriber",
+    skip(subscriber, list_name, base_url, confirmation_token, transaction)
 )]
-async fn send_confirmation_email(
-    email_client: &EmailClient,
+pub(crate) async fn enqueue_confirmation_email(
     subscriber: NewSubscriber,
+    list_name: &str,
     base_url: &ApplicationBaseUrl,
     confirmation_token: SubscriptionToken,
-) -> Result<(), reqwest::Error> {
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(), sqlx::Error> {
     let url = format!(
         "{}/subscriptions/confirm?subscription_token={}",
-        base_url.0, confirmation_token.0
+        base_url.0,
+        confirmation_token.as_ref()
     );
-    email_client
-        .send_email(
-            &subscriber.email,
-            "Welcome!",
-            &format!("Welcome to the newsletter! <br> Click <a href=\"{}\">here</a> to confirm your subscription", url),
-            &format!("Welcome to the newsletter!\nVisit {} to confirm your subscription", url),
-        )
-        .await
+    enqueue_email(
+        OutgoingEmail {
+            recipient: &subscriber.email,
+            subject: "Welcome!",
+            html_body: &format!(
+                "Welcome to {}! <br> Click <a href=\"{}\">here</a> to confirm your subscription",
+                list_name, url
+            ),
+            text_body: &format!(
+                "Welcome to {}!\nVisit {} to confirm your subscription",
+                list_name, url
+            ),
+        },
+        transaction,
+    )
+    .await
+    .map(|_| ())
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -201,8 +380,6 @@ pub struct ConfirmRegistrationParams {
 
 #[derive(thiserror::Error)]
 pub enum ConfirmationError {
-    #[error("{0}")]
-    ValidationError(String),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -216,62 +393,266 @@ impl Debug for ConfirmationError {
 impl ResponseError for ConfirmationError {
     fn status_code(&self) -> StatusCode {
         match self {
-            ConfirmationError::ValidationError(_) => StatusCode::BAD_REQUEST,
             ConfirmationError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
 
+/// What a subscriber is told after following a confirmation link.
+pub enum ConfirmationOutcome {
+    Confirmed { list_name: String },
+    AlreadyConfirmed { list_name: String },
+    UnknownToken,
+    ExpiredToken,
+}
+
+#[derive(serde::Serialize)]
+struct ConfirmationResponse {
+    status: &'static str,
+    message: String,
+}
+
+impl ConfirmationOutcome {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Confirmed { .. } | Self::AlreadyConfirmed { .. } => StatusCode::OK,
+            Self::UnknownToken => StatusCode::BAD_REQUEST,
+            Self::ExpiredToken => StatusCode::GONE,
+        }
+    }
+
+    fn status(&self) -> &'static str {
+        match self {
+            Self::Confirmed { .. } => "confirmed",
+            Self::AlreadyConfirmed { .. } => "already_confirmed",
+            Self::UnknownToken => "unknown_token",
+            Self::ExpiredToken => "expired_token",
+        }
+    }
+
+    fn heading(&self) -> &'static str {
+        match self {
+            Self::Confirmed { .. } => "Subscription confirmed",
+            Self::AlreadyConfirmed { .. } => "Already confirmed",
+            Self::UnknownToken => "Link not recognised",
+            Self::ExpiredToken => "Link expired",
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            Self::Confirmed { list_name } => format!("You are now subscribed to {}.", list_name),
+            Self::AlreadyConfirmed { list_name } => format!(
+                "Your subscription to {} has already been confirmed.",
+                list_name
+            ),
+            Self::UnknownToken => {
+                "This confirmation link is not valid or has already been used.".to_string()
+            }
+            Self::ExpiredToken => "This confirmation link has expired. \
+                Please subscribe again to receive a new confirmation email."
+                .to_string(),
+        }
+    }
+
+    fn respond(&self, request: &HttpRequest, branding: &BrandingSettings) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if accepts_json(request) {
+            return response.json(ConfirmationResponse {
+                status: self.status(),
+                message: self.message(),
+            });
+        }
+        response
+            .content_type("text/html; charset=utf-8")
+            .body(render_page(
+                branding,
+                self.heading(),
+                &format!("<p>{}</p>", escape_html(&self.message())),
+            ))
+    }
+}
+
+fn accepts_json(request: &HttpRequest) -> bool {
+    request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map(|accept| accept.contains("application/json"))
+        .unwrap_or(false)
+}
+
+// Mail scanners follow every link they see, so opening a confirmation link
+// only renders a page whose button submits the token back to us.
 #[allow(clippy::async_yields_async)]
-#[tracing::instrument(name = "confirming registration", skip(params, pool))]
-pub async fn confirm_registration(
+#[tracing::instrument(
+    name = "rendering confirmation page",
+    skip(params, request, pool, hmac_secret, branding)
+)]
+pub async fn confirmation_page(
     params: web::Query<ConfirmRegistrationParams>,
+    request: HttpRequest,
     pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    branding: web::Data<BrandingSettings>,
 ) -> Result<HttpResponse, ConfirmationError> {
+    let token_hash = hash_subscription_token(&params.subscription_token, &hmac_secret);
+    let list_name = match check_confirmation_token(&token_hash, pool.as_ref()).await? {
+        Ok(PendingConfirmation { list_name, .. }) => list_name,
+        Err(outcome) => return Ok(outcome.respond(&request, &branding)),
+    };
+
+    if accepts_json(&request) {
+        return Ok(HttpResponse::Ok().json(ConfirmationResponse {
+            status: "pending",
+            message: format!(
+                "Submit the token to confirm your subscription to {}.",
+                list_name
+            ),
+        }));
+    }
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(render_page(
+            &branding,
+            "Confirm your subscription",
+            &format!(
+                r#"<p>Please confirm your subscription to {}.</p>
+<form action="/subscriptions/confirm" method="post">
+<input type="hidden" name="subscription_token" value="{}">
+<button type="submit" style="background-color: {}; color: #ffffff">Confirm subscription</button>
+</form>"#,
+                escape_html(&list_name),
+                escape_html(&params.subscription_token),
+                escape_html(&branding.primary_color)
+            ),
+        )))
+}
+
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(
+    name = "confirming registration",
+    skip(params, request, pool, hmac_secret, branding)
+)]
+pub async fn confirm_registration(
+    params: web::Form<ConfirmRegistrationParams>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    branding: web::Data<BrandingSettings>,
+) -> Result<HttpResponse, ConfirmationError> {
+    let token_hash = hash_subscription_token(&params.subscription_token, &hmac_secret);
     let mut transaction = pool.begin().await.context("failed to create transaction")?;
 
-    let user_id = find_user_id_by_confirmation_token(&params, &mut transaction)
-        .await
-        .context("failed to query registration token")?
-        .ok_or_else(|| {
-            ConfirmationError::ValidationError("failed to find token in database".to_string())
-        })?;
+    let pending = match check_confirmation_token(&token_hash, &mut transaction).await? {
+        Ok(pending) => pending,
+        Err(outcome) => return Ok(outcome.respond(&request, &branding)),
+    };
 
-    update_user_status_to_confirmed(user_id, &mut transaction)
+    update_user_status_to_confirmed(pending.subscriber_id, pending.list_id, &mut transaction)
         .await
         .context("failed to update user's status to confirmed")?;
 
-    delete_used_subscription_token(&params, &mut transaction)
+    mark_subscription_token_as_used(&token_hash, &mut transaction)
         .await
-        .context("failed to remove used confirmation token")?;
+        .context("failed to mark confirmation token as used")?;
 
     transaction
         .commit()
         .await
         .context("failed to complete transaction")?;
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(ConfirmationOutcome::Confirmed {
+        list_name: pending.list_name,
+    }
+    .respond(&request, &branding))
 }
 
-#[tracing::instrument(name = "finding user id for confirmation token", skip(transaction))]
-async fn find_user_id_by_confirmation_token(
-    params: &ConfirmRegistrationParams,
-    transaction: &mut Transaction<'_, Postgres>,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    sqlx::query!(
-        "SELECT subscriber_id from subscription_tokens WHERE subscription_token = $1",
-        params.subscription_token
+struct PendingConfirmation {
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    list_name: String,
+}
+
+async fn check_confirmation_token<'c>(
+    token_hash: &str,
+    executor: impl PgExecutor<'c>,
+) -> Result<Result<PendingConfirmation, ConfirmationOutcome>, anyhow::Error> {
+    let token = match find_confirmation_token(token_hash, executor)
+        .await
+        .context("failed to query registration token")?
+    {
+        Some(token) => token,
+        None => return Ok(Err(ConfirmationOutcome::UnknownToken)),
+    };
+    if token.status == "confirmed" {
+        return Ok(Err(ConfirmationOutcome::AlreadyConfirmed {
+            list_name: token.list_name,
+        }));
+    }
+    if token.used_at.is_some() {
+        return Ok(Err(ConfirmationOutcome::UnknownToken));
+    }
+    if token.expires_at <= Utc::now() {
+        return Ok(Err(ConfirmationOutcome::ExpiredToken));
+    }
+    Ok(Ok(PendingConfirmation {
+        subscriber_id: token.subscriber_id,
+        list_id: token.list_id,
+        list_name: token.list_name,
+    }))
+}
+
+struct StoredConfirmationToken {
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    list_name: String,
+    status: String,
+    expires_at: DateTime<Utc>,
+    used_at: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(name = "finding confirmation token", skip(token_hash, executor))]
+async fn find_confirmation_token<'c>(
+    token_hash: &str,
+    executor: impl PgExecutor<'c>,
+) -> Result<Option<StoredConfirmationToken>, sqlx::Error> {
+    sqlx::query_as!(
+        StoredConfirmationToken,
+        r#"
+        SELECT
+            subscription_tokens.subscriber_id,
+            subscription_tokens.list_id,
+            lists.name AS list_name,
+            list_subscriptions.status,
+            subscription_tokens.expires_at,
+            subscription_tokens.used_at
+        FROM subscription_tokens
+        JOIN lists ON lists.list_id = subscription_tokens.list_id
+        JOIN list_subscriptions
+            ON list_subscriptions.list_id = subscription_tokens.list_id
+            AND list_subscriptions.subscriber_id = subscription_tokens.subscriber_id
+        WHERE subscription_token_hash = $1
+        "#,
+        token_hash
     )
-    .fetch_optional(transaction)
+    .fetch_optional(executor)
     .await
-    .map(|r| r.map(|v| v.subscriber_id))
 }
 
 #[tracing::instrument(name = "updating user id to confirmed", skip(transaction))]
 async fn update_user_status_to_confirmed(
     user_id: Uuid,
+    list_id: Uuid,
     transaction: &mut Transaction<'_, Postgres>,
 ) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE list_subscriptions SET status = 'confirmed' WHERE subscriber_id = $1 AND list_id = $2",
+        user_id,
+        list_id
+    )
+    .execute(&mut *transaction)
+    .await?;
     sqlx::query!(
         "UPDATE subscriptions SET status = 'confirmed' WHERE id = $1",
         user_id
@@ -281,27 +662,20 @@ async fn update_user_status_to_confirmed(
     .map(|_| ())
 }
 
-#[tracing::instrument(name = "deleting used confirmation token", skip(transaction))]
-async fn delete_used_subscription_token(
-    params: &ConfirmRegistrationParams,
+#[tracing::instrument(
+    name = "marking confirmation token as used",
+    skip(token_hash, transaction)
+)]
+async fn mark_subscription_token_as_used(
+    token_hash: &str,
     transaction: &mut Transaction<'_, Postgres>,
 ) -> Result<(), sqlx::Error> {
     sqlx::query!(
-        "DELETE FROM subscription_tokens WHERE subscription_token = $1",
-        params.subscription_token
+        "UPDATE subscription_tokens SET used_at = $2 WHERE subscription_token_hash = $1",
+        token_hash,
+        Utc::now()
     )
     .execute(transaction)
     .await
     .map(|_| ())
 }
-
-struct SubscriptionToken(String);
-
-fn generate_subscription_token() -> SubscriptionToken {
-    let mut rng = thread_rng();
-    let inner = std::iter::repeat_with(|| rng.sample(Alphanumeric))
-        .map(char::from)
-        .take(25)
-        .collect();
-    SubscriptionToken(inner)
-}
@@ -0,0 +1,137 @@
+use std::fmt::Debug;
+
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use sqlx::PgPool;
+use tracing::{field::display, Span};
+
+use crate::{
+    branding::render_page, common::error_chain_fmt, configuration::BrandingSettings,
+    startup::HmacSecret, template::escape_html, unsubscribe_token::UnsubscribeToken,
+};
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParams {
+    // Missing tokens get the same page as invalid ones.
+    #[serde(default)]
+    token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {}
+
+// Invalid links get a branded page rather than an `UnsubscribeError`, as
+// `ResponseError` has no access to the branding.
+fn verify_token(
+    params: &UnsubscribeParams,
+    hmac_secret: &HmacSecret,
+    branding: &BrandingSettings,
+) -> Result<UnsubscribeToken, HttpResponse> {
+    let token = UnsubscribeToken::verify(&params.token, hmac_secret).map_err(|e| {
+        tracing::warn!(error.cause_chain = ?e, "rejecting an invalid unsubscribe token");
+        HttpResponse::BadRequest()
+            .content_type("text/html; charset=utf-8")
+            .body(render_page(
+                branding,
+                "Invalid link",
+                "<p>This unsubscribe link is not valid.</p>",
+            ))
+    })?;
+    Span::current()
+        .record("subscriber_id", &display(token.subscriber_id))
+        .record("list_id", &display(token.list_id));
+    Ok(token)
+}
+
+// Mail scanners and link prefetchers follow every link they see, so opening
+// the footer link only renders a page whose button posts the token back.
+#[tracing::instrument(
+    name = "rendering unsubscribe page",
+    skip(params, hmac_secret, branding),
+    fields(subscriber_id = tracing::field::Empty, list_id = tracing::field::Empty)
+)]
+pub async fn unsubscribe_page(
+    params: web::Query<UnsubscribeParams>,
+    hmac_secret: web::Data<HmacSecret>,
+    branding: web::Data<BrandingSettings>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    if let Err(response) = verify_token(&params, &hmac_secret, &branding) {
+        return Ok(response);
+    }
+
+    // Verified tokens are URL-safe base64, they can go in the URL as they are.
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(render_page(
+            &branding,
+            "Unsubscribe",
+            &format!(
+                r#"<p>Do you want to stop receiving this newsletter?</p>
+<form action="/subscriptions/unsubscribe?token={}" method="post">
+<input type="hidden" name="List-Unsubscribe" value="One-Click">
+<button type="submit" style="background-color: {}; color: #ffffff">Unsubscribe</button>
+</form>"#,
+                escape_html(&params.token),
+                escape_html(&branding.primary_color)
+            ),
+        )))
+}
+
+// Serves both the button of the unsubscribe page and RFC 8058 one-click
+// requests, which carry the token in the URL and `List-Unsubscribe=One-Click`
+// as a body.
+#[tracing::instrument(
+    name = "unsubscribing from a list",
+    skip(params, pool, hmac_secret, branding),
+    fields(subscriber_id = tracing::field::Empty, list_id = tracing::field::Empty)
+)]
+pub async fn unsubscribe(
+    params: web::Query<UnsubscribeParams>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    branding: web::Data<BrandingSettings>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let token = match verify_token(&params, &hmac_secret, &branding) {
+        Ok(token) => token,
+        Err(response) => return Ok(response),
+    };
+
+    mark_as_unsubscribed(&token, &pool)
+        .await
+        .context("failed to unsubscribe from the list")?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(render_page(
+            &branding,
+            "Unsubscribed",
+            "<p>You have been unsubscribed.</p>",
+        )))
+}
+
+#[tracing::instrument(name = "marking list subscription as unsubscribed", skip(token, pool))]
+async fn mark_as_unsubscribed(token: &UnsubscribeToken, pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE list_subscriptions
+        SET status = 'unsubscribed'
+        WHERE subscriber_id = $1 AND list_id = $2
+        "#,
+        token.subscriber_id,
+        token.list_id
+    )
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
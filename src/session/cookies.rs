@@ -0,0 +1,110 @@
+use actix_web::{
+    cookie::{Cookie, SameSite},
+    HttpRequest,
+};
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::startup::HmacSecret;
+
+pub const SESSION_COOKIE: &str = "session";
+pub const FLASH_COOKIE: &str = "flash";
+
+pub fn session_cookie(session_id: Uuid, secret: &HmacSecret, secure: bool) -> Cookie<'static> {
+    Cookie::build(
+        SESSION_COOKIE,
+        sign(SESSION_COOKIE, &session_id.to_string(), secret),
+    )
+    .path("/")
+    .http_only(true)
+    .same_site(SameSite::Strict)
+    .secure(secure)
+    .finish()
+}
+
+/// The session id carried by a session cookie, if it has a valid signature.
+pub fn session_id(cookie: &Cookie<'_>, secret: &HmacSecret) -> Option<Uuid> {
+    verify(SESSION_COOKIE, cookie.value(), secret)?.parse().ok()
+}
+
+/// A message shown once by the next page that reads it, usually after a redirect.
+pub fn flash_cookie(message: &str, secret: &HmacSecret, secure: bool) -> Cookie<'static> {
+    let encoded = base64::encode_config(message, base64::URL_SAFE_NO_PAD);
+    Cookie::build(FLASH_COOKIE, sign(FLASH_COOKIE, &encoded, secret))
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .secure(secure)
+        .finish()
+}
+
+pub fn flash_message(request: &HttpRequest, secret: &HmacSecret) -> Option<String> {
+    let cookie = request.cookie(FLASH_COOKIE)?;
+    let encoded = verify(FLASH_COOKIE, cookie.value(), secret)?;
+    let decoded = base64::decode_config(encoded, base64::URL_SAFE_NO_PAD).ok()?;
+    String::from_utf8(decoded).ok()
+}
+
+/// A cookie matching the ones above, to be passed to `del_cookie`.
+pub fn removal_cookie(name: &'static str) -> Cookie<'static> {
+    Cookie::build(name, "").path("/").finish()
+}
+
+fn sign(purpose: &str, value: &str, secret: &HmacSecret) -> String {
+    let signature = mac(purpose, value, secret).finalize().into_bytes();
+    format!(
+        "{}.{}",
+        value,
+        base64::encode_config(signature, base64::URL_SAFE_NO_PAD)
+    )
+}
+
+fn verify(purpose: &str, signed: &str, secret: &HmacSecret) -> Option<String> {
+    let (value, signature) = signed.rsplit_once('.')?;
+    let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).ok()?;
+    mac(purpose, value, secret).verify(&signature).ok()?;
+    Some(value.to_string())
+}
+
+fn mac(purpose: &str, value: &str, secret: &HmacSecret) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.0.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(purpose.as_bytes());
+    mac.update(b":");
+    mac.update(value.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sign, verify};
+    use crate::startup::HmacSecret;
+
+    fn secret() -> HmacSecret {
+        HmacSecret("a-secret".to_string())
+    }
+
+    #[test]
+    fn signed_values_can_be_verified() {
+        let signed = sign("session", "some-id", &secret());
+        assert_eq!(
+            verify("session", &signed, &secret()).as_deref(),
+            Some("some-id")
+        );
+    }
+
+    #[test]
+    fn tampered_values_are_rejected() {
+        let signed = sign("session", "some-id", &secret());
+        let tampered = signed.replacen("some-id", "other-id", 1);
+
+        assert_eq!(verify("session", &tampered, &secret()), None);
+        assert_eq!(verify("flash", &signed, &secret()), None);
+        assert_eq!(
+            verify("session", &signed, &HmacSecret("other".to_string())),
+            None
+        );
+        assert_eq!(verify("session", "no-signature", &secret()), None);
+    }
+}
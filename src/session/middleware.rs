@@ -0,0 +1,87 @@
+use std::rc::Rc;
+
+use actix_service::{forward_ready, Service, Transform};
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    error::ErrorInternalServerError,
+    http::header,
+    web, Error, HttpMessage, HttpResponse,
+};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use sqlx::PgPool;
+
+use super::{load_session, session_id, SESSION_COOKIE};
+use crate::startup::HmacSecret;
+
+/// Guards a scope behind a login session.
+///
+/// Requests with a valid session cookie get a `SessionUser` in their
+/// extensions. Requests with an `Authorization` header are let through for
+/// the handler to check their credentials, everything else is sent to the
+/// login form.
+pub struct RequireSession;
+
+impl<S> Transform<S, ServiceRequest> for RequireSession
+where
+    S: Service<ServiceRequest, Response = ServiceResponse, Error = Error> + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Transform = RequireSessionMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireSessionMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RequireSessionMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S> Service<ServiceRequest> for RequireSessionMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse, Error = Error> + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        Box::pin(async move {
+            let session_id = match (
+                req.cookie(SESSION_COOKIE),
+                req.app_data::<web::Data<HmacSecret>>(),
+            ) {
+                (Some(cookie), Some(secret)) => session_id(&cookie, secret),
+                _ => None,
+            };
+            let session = match (session_id, req.app_data::<web::Data<PgPool>>()) {
+                (Some(session_id), Some(pool)) => load_session(pool, session_id)
+                    .await
+                    .map_err(ErrorInternalServerError)?,
+                _ => None,
+            };
+
+            match session {
+                Some(session) => {
+                    req.extensions_mut().insert(session);
+                }
+                None if req.headers().contains_key(header::AUTHORIZATION) => {}
+                None => {
+                    let response = HttpResponse::SeeOther()
+                        .insert_header((header::LOCATION, "/login"))
+                        .finish();
+                    return Ok(req.into_response(response));
+                }
+            }
+            service.call(req).await
+        })
+    }
+}
@@ -0,0 +1,10 @@
+mod cookies;
+mod middleware;
+mod store;
+
+pub use cookies::{
+    flash_cookie, flash_message, removal_cookie, session_cookie, session_id, FLASH_COOKIE,
+    SESSION_COOKIE,
+};
+pub use middleware::RequireSession;
+pub use store::{create_session, delete_session, load_session, SessionUser};
@@ -0,0 +1,73 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Stored in the request extensions by `RequireSession` once a session checks out.
+#[derive(Clone, Copy, Debug)]
+pub struct SessionUser {
+    pub session_id: Uuid,
+    pub user_id: Uuid,
+}
+
+#[tracing::instrument(name = "creating a session", skip(pool, expiration))]
+pub async fn create_session(
+    pool: &PgPool,
+    user_id: Uuid,
+    expiration: Duration,
+) -> Result<Uuid, anyhow::Error> {
+    let session_id = Uuid::new_v4();
+    let created_at = Utc::now();
+    let expires_at = created_at
+        + chrono::Duration::from_std(expiration).context("session expiration is out of range")?;
+
+    sqlx::query!("DELETE FROM sessions WHERE expires_at <= $1", created_at)
+        .execute(pool)
+        .await
+        .context("failed to delete expired sessions")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO sessions (session_id, user_id, created_at, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        session_id,
+        user_id,
+        created_at,
+        expires_at
+    )
+    .execute(pool)
+    .await
+    .context("failed to store session")?;
+
+    Ok(session_id)
+}
+
+#[tracing::instrument(name = "loading a session", skip(pool, session_id))]
+pub async fn load_session(
+    pool: &PgPool,
+    session_id: Uuid,
+) -> Result<Option<SessionUser>, sqlx::Error> {
+    sqlx::query!(
+        "SELECT user_id FROM sessions WHERE session_id = $1 AND expires_at > $2",
+        session_id,
+        Utc::now()
+    )
+    .fetch_optional(pool)
+    .await
+    .map(|r| {
+        r.map(|r| SessionUser {
+            session_id,
+            user_id: r.user_id,
+        })
+    })
+}
+
+#[tracing::instrument(name = "deleting a session", skip(pool, session_id))]
+pub async fn delete_session(pool: &PgPool, session_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM sessions WHERE session_id = $1", session_id)
+        .execute(pool)
+        .await
+        .map(|_| ())
+}
@@ -1,48 +1,100 @@
-use std::net::TcpListener;
+use std::{net::TcpListener, time::Duration};
 
 use actix_web::{
     dev::Server,
-    web::{get, post, Data},
+    web::{delete, get, post, put, resource, scope, Data, PayloadConfig},
     App, HttpServer,
 };
 use sqlx::{postgres::PgPoolOptions, PgPool};
 use tracing_actix_web::TracingLogger;
 
 use crate::{
-    configuration::{DatabaseSettings, Settings},
+    authentication::LoginThrottle,
+    configuration::{BrandingSettings, DatabaseSettings, Settings},
     email_client::EmailClient,
-    routes::{confirm_registration, health_check, publish_newsletter, subscribe},
+    email_outbox::OutboxDispatcher,
+    issue_delivery_worker::IssueDeliveryWorker,
+    newsletter_scheduler::NewsletterScheduler,
+    routes::{
+        admin_dashboard, cancel_scheduled_newsletter, change_admin_password, change_password_form,
+        confirm_registration, confirmation_page, create_api_token, delete_subscriber,
+        export_subscribers, forgot_password, forgot_password_form, get_subscriber, health_check,
+        import_subscribers, list_api_tokens, list_dead_letters, list_scheduled_newsletters,
+        list_subscribers, log_out, login, login_form, publish_newsletter, replay_dead_letters,
+        reschedule_newsletter, reset_password, reset_password_form, revoke_api_token, subscribe,
+        subscriber_stats, unsubscribe, unsubscribe_page, update_subscriber_status,
+    },
+    session::RequireSession,
+    subscription_cleanup::SubscriptionCleanup,
+    subscription_token::hash_outstanding_tokens,
 };
 
 pub struct Application {
     port: u16,
     server: Server,
+    delivery_worker: IssueDeliveryWorker,
+    scheduler: NewsletterScheduler,
+    subscription_cleanup: SubscriptionCleanup,
+    outbox_dispatcher: OutboxDispatcher,
 }
 
 impl Application {
     pub async fn build(config: Settings) -> Result<Self, std::io::Error> {
+        let max_concurrent_sends = config.email_client.max_concurrent_sends;
         let pg_pool = get_connection_pool(&config.database)
             .await
             .expect("failed to connect to postgres");
+        let hmac_secret = HmacSecret(config.application.hmac_secret.clone());
+        hash_outstanding_tokens(&pg_pool, &hmac_secret)
+            .await
+            .expect("failed to hash outstanding subscription tokens");
 
-        let sender_email = config
-            .email_client
-            .sender()
-            .expect("should be a valid email address");
-
-        let email_client = EmailClient::new(
-            config.email_client.base_url,
-            sender_email,
-            config.email_client.auth_token,
-        );
+        let email_client = config.email_client.client();
 
         let address = format!("{}:{}", config.application.host, config.application.port);
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
 
-        let server = run_on(listener, pg_pool, email_client, config.application.base_url)?;
+        let delivery_worker = IssueDeliveryWorker::new(
+            pg_pool.clone(),
+            email_client.clone(),
+            config.application.base_url.clone(),
+            hmac_secret.clone(),
+            max_concurrent_sends,
+        );
+        let scheduler = NewsletterScheduler::new(pg_pool.clone());
+        let outbox_dispatcher = OutboxDispatcher::new(pg_pool.clone(), email_client.clone());
+        let subscription_cleanup = SubscriptionCleanup::new(
+            pg_pool.clone(),
+            config.application.pending_subscriber_retention(),
+        );
+
+        let idempotency_key_expiration = config.application.idempotency_key_expiration();
+        let subscription_token_expiration = config.application.subscription_token_expiration();
+        let session_expiration = config.application.session_expiration();
+        let password_reset_token_expiration = config.application.password_reset_token_expiration();
+        let server = run_on(
+            listener,
+            pg_pool,
+            email_client,
+            config.application.base_url,
+            hmac_secret,
+            idempotency_key_expiration,
+            subscription_token_expiration,
+            session_expiration,
+            password_reset_token_expiration,
+            config.branding,
+            LoginThrottle::new(config.login_throttle),
+        )?;
 
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            delivery_worker,
+            scheduler,
+            subscription_cleanup,
+            outbox_dispatcher,
+        })
     }
 
     pub fn port(&self) -> u16 {
@@ -50,38 +102,150 @@ impl Application {
     }
 
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        let delivery_worker = actix_web::rt::spawn(self.delivery_worker.run_until_stopped());
+        let scheduler = actix_web::rt::spawn(self.scheduler.run_until_stopped());
+        let subscription_cleanup =
+            actix_web::rt::spawn(self.subscription_cleanup.run_until_stopped());
+        let outbox_dispatcher = actix_web::rt::spawn(self.outbox_dispatcher.run_until_stopped());
+        let outcome = self.server.await;
+        delivery_worker.abort();
+        scheduler.abort();
+        subscription_cleanup.abort();
+        outbox_dispatcher.abort();
+        outcome
     }
 }
 
 pub async fn get_connection_pool(config: &DatabaseSettings) -> Result<PgPool, sqlx::Error> {
     PgPoolOptions::new()
-        .connect_timeout(std::time::Duration::from_secs(2))
+        .max_connections(config.max_connections)
+        .connect_timeout(Duration::from_secs(2))
         .connect_with(config.with_db())
         .await
 }
 
+// Enough for a CSV upload of a few hundred thousand subscribers.
+const IMPORT_PAYLOAD_LIMIT: usize = 32 * 1024 * 1024;
+
 pub struct ApplicationBaseUrl(pub String);
 
+impl ApplicationBaseUrl {
+    /// Whether cookies can be restricted to HTTPS.
+    pub fn is_https(&self) -> bool {
+        self.0.starts_with("https://")
+    }
+}
+
+#[derive(Clone)]
+pub struct HmacSecret(pub String);
+
+pub struct IdempotencyKeyExpiration(pub Duration);
+
+pub struct SubscriptionTokenExpiration(pub Duration);
+
+pub struct SessionExpiration(pub Duration);
+
+pub struct PasswordResetTokenExpiration(pub Duration);
+
+#[allow(clippy::too_many_arguments)]
 pub fn run_on(
     listener: TcpListener,
     pool: PgPool,
     email_client: EmailClient,
     base_url: String,
+    hmac_secret: HmacSecret,
+    idempotency_key_expiration: Duration,
+    subscription_token_expiration: Duration,
+    session_expiration: Duration,
+    password_reset_token_expiration: Duration,
+    branding: BrandingSettings,
+    login_throttle: LoginThrottle,
 ) -> Result<Server, std::io::Error> {
     let pool = Data::new(pool);
     let email_client = Data::new(email_client);
     let base_url = Data::new(ApplicationBaseUrl(base_url));
+    let hmac_secret = Data::new(hmac_secret);
+    let idempotency_key_expiration =
+        Data::new(IdempotencyKeyExpiration(idempotency_key_expiration));
+    let subscription_token_expiration =
+        Data::new(SubscriptionTokenExpiration(subscription_token_expiration));
+    let session_expiration = Data::new(SessionExpiration(session_expiration));
+    let password_reset_token_expiration = Data::new(PasswordResetTokenExpiration(
+        password_reset_token_expiration,
+    ));
+    let branding = Data::new(branding);
+    let login_throttle = Data::new(login_throttle);
     let server = HttpServer::new(move || {
         App::new()
             .wrap(TracingLogger::default())
             .route("/health_check", get().to(health_check))
             .route("/subscriptions", post().to(subscribe))
-            .route("/subscriptions/confirm", get().to(confirm_registration))
+            .route("/subscriptions/confirm", get().to(confirmation_page))
+            .route("/subscriptions/confirm", post().to(confirm_registration))
+            .route("/subscriptions/unsubscribe", get().to(unsubscribe_page))
+            .route("/subscriptions/unsubscribe", post().to(unsubscribe))
             .route("/newsletter", post().to(publish_newsletter))
+            .route("/login", get().to(login_form))
+            .route("/login", post().to(login))
+            .route("/logout", post().to(log_out))
+            .route("/password/forgot", get().to(forgot_password_form))
+            .route("/password/forgot", post().to(forgot_password))
+            .route("/password/reset", get().to(reset_password_form))
+            .route("/password/reset", post().to(reset_password))
+            .service(
+                scope("/admin")
+                    .wrap(RequireSession)
+                    .route("/dashboard", get().to(admin_dashboard))
+                    .route("/password", get().to(change_password_form))
+                    .route("/password", post().to(change_admin_password))
+                    .route("/tokens", get().to(list_api_tokens))
+                    .route("/tokens", post().to(create_api_token))
+                    .route("/tokens/{token_id}", delete().to(revoke_api_token))
+                    .route("/subscribers", get().to(list_subscribers))
+                    .route("/subscribers/export", get().to(export_subscribers))
+                    .route("/subscribers/stats", get().to(subscriber_stats))
+                    .service(
+                        resource("/subscribers/import")
+                            .app_data(PayloadConfig::new(IMPORT_PAYLOAD_LIMIT))
+                            .route(post().to(import_subscribers)),
+                    )
+                    .route("/subscribers/{subscriber_id}", get().to(get_subscriber))
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        delete().to(delete_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/status",
+                        put().to(update_subscriber_status),
+                    ),
+            )
+            .route(
+                "/newsletter/scheduled",
+                get().to(list_scheduled_newsletters),
+            )
+            .route(
+                "/newsletter/scheduled/{newsletter_issue_id}",
+                put().to(reschedule_newsletter),
+            )
+            .route(
+                "/newsletter/scheduled/{newsletter_issue_id}",
+                delete().to(cancel_scheduled_newsletter),
+            )
+            .route("/newsletter/dead_letters", get().to(list_dead_letters))
+            .route(
+                "/newsletter/dead_letters/replay",
+                post().to(replay_dead_letters),
+            )
             .app_data(Data::clone(&pool))
             .app_data(Data::clone(&email_client))
             .app_data(Data::clone(&base_url))
+            .app_data(Data::clone(&hmac_secret))
+            .app_data(Data::clone(&idempotency_key_expiration))
+            .app_data(Data::clone(&subscription_token_expiration))
+            .app_data(Data::clone(&session_expiration))
+            .app_data(Data::clone(&password_reset_token_expiration))
+            .app_data(Data::clone(&branding))
+            .app_data(Data::clone(&login_throttle))
     })
     .listen(listener)?
     .run();
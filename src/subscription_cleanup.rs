@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub struct SubscriptionCleanup {
+    pool: PgPool,
+    pending_subscriber_retention: Duration,
+}
+
+impl SubscriptionCleanup {
+    pub fn new(pool: PgPool, pending_subscriber_retention: Duration) -> Self {
+        Self {
+            pool,
+            pending_subscriber_retention,
+        }
+    }
+
+    pub async fn run_until_stopped(self) {
+        loop {
+            let _ = purge_stale_subscriptions(&self.pool, self.pending_subscriber_retention).await;
+            actix_web::rt::time::sleep(PURGE_INTERVAL).await;
+        }
+    }
+}
+
+pub struct PurgeOutcome {
+    pub expired_tokens: u64,
+    pub pending_subscribers: u64,
+}
+
+#[tracing::instrument(
+    name = "purging stale subscriptions",
+    skip(pool),
+    fields(
+        expired_tokens = tracing::field::Empty,
+        pending_subscribers = tracing::field::Empty
+    ),
+    err
+)]
+pub async fn purge_stale_subscriptions(
+    pool: &PgPool,
+    pending_subscriber_retention: Duration,
+) -> Result<PurgeOutcome, anyhow::Error> {
+    let now = Utc::now();
+    let cutoff = now
+        - chrono::Duration::from_std(pending_subscriber_retention)
+            .context("pending subscriber retention is out of range")?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to retrieve connection from pool")?;
+
+    // Expired tokens are kept for the retention period too, so that their
+    // links keep explaining they have expired rather than being unknown.
+    let expired_tokens = sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE expires_at <= $1",
+        cutoff
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to delete expired subscription tokens")?
+    .rows_affected();
+
+    // Subscribers that never confirmed a single list are removed together with
+    // everything that references them, unless they still hold a live token.
+    let stale_subscribers = sqlx::query!(
+        r#"
+        SELECT id FROM subscriptions
+        WHERE status = 'pending'
+            AND subscribed_at < $1
+            AND NOT EXISTS (
+                SELECT 1 FROM subscription_tokens
+                WHERE subscription_tokens.subscriber_id = subscriptions.id
+                    AND subscription_tokens.expires_at > $2
+            )
+        "#,
+        cutoff,
+        now
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("failed to query stale pending subscribers")?
+    .into_iter()
+    .map(|r| r.id)
+    .collect::<Vec<_>>();
+
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = ANY($1)",
+        &stale_subscribers
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to delete tokens of stale pending subscribers")?;
+    sqlx::query!(
+        "DELETE FROM list_subscriptions WHERE subscriber_id = ANY($1)",
+        &stale_subscribers
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to delete list subscriptions of stale pending subscribers")?;
+    sqlx::query!(
+        "DELETE FROM subscriber_attributes WHERE subscriber_id = ANY($1)",
+        &stale_subscribers
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to delete attributes of stale pending subscribers")?;
+    let pending_subscribers = sqlx::query!(
+        "DELETE FROM subscriptions WHERE id = ANY($1)",
+        &stale_subscribers
+    )
+    .execute(&mut transaction)
+    .await
+    .context("failed to delete stale pending subscribers")?
+    .rows_affected();
+
+    transaction
+        .commit()
+        .await
+        .context("failed to complete transaction")?;
+
+    tracing::Span::current()
+        .record("expired_tokens", &expired_tokens)
+        .record("pending_subscribers", &pending_subscribers);
+    Ok(PurgeOutcome {
+        expired_tokens,
+        pending_subscribers,
+    })
+}
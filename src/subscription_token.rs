@@ -0,0 +1,109 @@
+use anyhow::Context;
+use hmac::{Hmac, Mac, NewMac};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sha2::Sha256;
+use sqlx::PgPool;
+
+use crate::startup::HmacSecret;
+
+pub struct SubscriptionToken(String);
+
+impl SubscriptionToken {
+    pub fn generate() -> Self {
+        let mut rng = thread_rng();
+        let inner = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+            .map(char::from)
+            .take(25)
+            .collect();
+        Self(inner)
+    }
+
+    pub fn hash(&self, secret: &HmacSecret) -> String {
+        hash_subscription_token(&self.0, secret)
+    }
+}
+
+impl AsRef<str> for SubscriptionToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+pub fn hash_subscription_token(token: &str, secret: &HmacSecret) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.0.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(b"subscription_token:");
+    mac.update(token.as_bytes());
+    base64::encode(mac.finalize().into_bytes())
+}
+
+// Tokens issued before they were stored hashed are hashed on start-up, as the
+// secret needed to do so is not available to the database migrations.
+#[tracing::instrument(name = "hashing outstanding subscription tokens", skip(pool, secret))]
+pub async fn hash_outstanding_tokens(
+    pool: &PgPool,
+    secret: &HmacSecret,
+) -> Result<u64, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to retrieve connection from pool")?;
+
+    let tokens = sqlx::query!(
+        r#"
+        SELECT subscription_token as "subscription_token!"
+        FROM subscription_tokens
+        WHERE subscription_token IS NOT NULL
+        FOR UPDATE
+        "#
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("failed to query plaintext subscription tokens")?;
+
+    for token in &tokens {
+        sqlx::query!(
+            r#"
+            UPDATE subscription_tokens
+            SET subscription_token_hash = $2, subscription_token = NULL
+            WHERE subscription_token = $1
+            "#,
+            token.subscription_token,
+            hash_subscription_token(&token.subscription_token, secret)
+        )
+        .execute(&mut transaction)
+        .await
+        .context("failed to hash a plaintext subscription token")?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("failed to complete transaction")?;
+    Ok(tokens.len() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash_subscription_token, SubscriptionToken};
+    use crate::startup::HmacSecret;
+
+    #[test]
+    fn hashes_depend_on_the_token_and_the_secret() {
+        let secret = HmacSecret("a-secret".to_string());
+        let token = SubscriptionToken::generate();
+
+        assert_eq!(
+            token.hash(&secret),
+            hash_subscription_token(token.as_ref(), &secret)
+        );
+        assert_ne!(
+            token.hash(&secret),
+            SubscriptionToken::generate().hash(&secret)
+        );
+        assert_ne!(
+            token.hash(&secret),
+            token.hash(&HmacSecret("another-secret".to_string()))
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    Variable(Variable),
+}
+
+#[derive(Debug, PartialEq)]
+enum Variable {
+    Name,
+    Email,
+    UnsubscribeUrl,
+    Attribute(String),
+}
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum TemplateError {
+    #[error("a `{{{{` opened at byte {0} is never closed")]
+    UnclosedTag(usize),
+    #[error("unknown variable `{0}`")]
+    UnknownVariable(String),
+}
+
+pub struct MergeFields<'a> {
+    pub name: &'a str,
+    pub email: &'a str,
+    pub unsubscribe_url: &'a str,
+    pub attributes: &'a HashMap<String, String>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, TemplateError> {
+        let mut parts = Vec::new();
+        let mut rest = source;
+        let mut offset = 0;
+
+        while let Some(open) = rest.find("{{") {
+            if open > 0 {
+                parts.push(Part::Literal(rest[..open].to_string()));
+            }
+            let after_open = &rest[open + 2..];
+            let close = after_open
+                .find("}}")
+                .ok_or(TemplateError::UnclosedTag(offset + open))?;
+            parts.push(Part::Variable(Variable::parse(after_open[..close].trim())?));
+
+            let consumed = open + 2 + close + 2;
+            offset += consumed;
+            rest = &rest[consumed..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+
+        Ok(Self { parts })
+    }
+
+    pub fn render_text(&self, fields: &MergeFields) -> String {
+        self.render(fields, |value| value.to_string())
+    }
+
+    pub fn render_html(&self, fields: &MergeFields) -> String {
+        self.render(fields, escape_html)
+    }
+
+    fn render(&self, fields: &MergeFields, escape: impl Fn(&str) -> String) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(literal) => literal.clone(),
+                Part::Variable(variable) => escape(variable.value(fields)),
+            })
+            .collect()
+    }
+}
+
+impl Variable {
+    fn parse(expression: &str) -> Result<Self, TemplateError> {
+        match expression {
+            "name" => Ok(Self::Name),
+            "email" => Ok(Self::Email),
+            "unsubscribe_url" => Ok(Self::UnsubscribeUrl),
+            _ => match expression.strip_prefix("attributes.") {
+                Some(attribute) if is_identifier(attribute) => {
+                    Ok(Self::Attribute(attribute.to_string()))
+                }
+                _ => Err(TemplateError::UnknownVariable(expression.to_string())),
+            },
+        }
+    }
+
+    fn value<'a>(&self, fields: &'a MergeFields) -> &'a str {
+        match self {
+            Self::Name => fields.name,
+            Self::Email => fields.email,
+            Self::UnsubscribeUrl => fields.unsubscribe_url,
+            Self::Attribute(attribute) => fields
+                .attributes
+                .get(attribute)
+                .map(String::as_str)
+                .unwrap_or(""),
+        }
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+pub fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use claim::assert_ok;
+
+    use super::{MergeFields, Template, TemplateError};
+
+    fn render(source: &str, attributes: &HashMap<String, String>) -> String {
+        let fields = MergeFields {
+            name: "Joseph <Jo>",
+            email: "joseph@google.com",
+            unsubscribe_url: "https://example.com/unsubscribe",
+            attributes,
+        };
+        Template::parse(source).unwrap().render_html(&fields)
+    }
+
+    #[test]
+    fn merge_fields_are_substituted() {
+        let rendered = render(
+            "Hi {{ name }} ({{email}}), leave at {{ unsubscribe_url }}",
+            &HashMap::new(),
+        );
+        assert_eq!(
+            rendered,
+            "Hi Joseph &lt;Jo&gt; (joseph@google.com), leave at https://example.com/unsubscribe"
+        );
+    }
+
+    #[test]
+    fn custom_attributes_are_substituted_and_default_to_empty() {
+        let mut attributes = HashMap::new();
+        attributes.insert("company".to_string(), "Acme".to_string());
+        let rendered = render(
+            "{{ attributes.company }}/{{ attributes.city }}",
+            &attributes,
+        );
+        assert_eq!(rendered, "Acme/");
+    }
+
+    #[test]
+    fn text_rendering_does_not_escape() {
+        let attributes = HashMap::new();
+        let fields = MergeFields {
+            name: "Joseph <Jo>",
+            email: "joseph@google.com",
+            unsubscribe_url: "",
+            attributes: &attributes,
+        };
+        let template = Template::parse("Hi {{ name }}").unwrap();
+        assert_eq!(template.render_text(&fields), "Hi Joseph <Jo>");
+    }
+
+    #[test]
+    fn templates_without_tags_are_valid() {
+        assert_ok!(Template::parse("plain text with a single { brace }"));
+    }
+
+    #[test]
+    fn unknown_variables_are_rejected() {
+        for source in [
+            "{{ nme }}",
+            "{{ }}",
+            "{{ attributes. }}",
+            "{{ attributes.a b }}",
+        ] {
+            assert!(
+                matches!(
+                    Template::parse(source),
+                    Err(TemplateError::UnknownVariable(_))
+                ),
+                "{} was accepted",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn unclosed_tags_are_rejected() {
+        assert_eq!(
+            Template::parse("Hi {{ name }}, {{ email"),
+            Err(TemplateError::UnclosedTag(15))
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use std::convert::TryInto;
+
+use anyhow::{anyhow, Context};
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::startup::HmacSecret;
+
+const PAYLOAD_LENGTH: usize = 32;
+
+#[derive(Debug, PartialEq)]
+pub struct UnsubscribeToken {
+    pub subscriber_id: Uuid,
+    pub list_id: Uuid,
+}
+
+impl UnsubscribeToken {
+    pub fn new(subscriber_id: Uuid, list_id: Uuid) -> Self {
+        Self {
+            subscriber_id,
+            list_id,
+        }
+    }
+
+    pub fn sign(&self, secret: &HmacSecret) -> String {
+        let payload = self.payload();
+        let mut token = payload.to_vec();
+        token.extend_from_slice(&mac(secret, &payload).finalize().into_bytes());
+        base64::encode_config(token, base64::URL_SAFE_NO_PAD)
+    }
+
+    pub fn verify(token: &str, secret: &HmacSecret) -> Result<Self, anyhow::Error> {
+        let token = base64::decode_config(token, base64::URL_SAFE_NO_PAD)
+            .context("the unsubscribe token is not valid base64")?;
+        if token.len() <= PAYLOAD_LENGTH {
+            return Err(anyhow!("the unsubscribe token is too short"));
+        }
+        let (payload, signature) = token.split_at(PAYLOAD_LENGTH);
+        mac(secret, payload)
+            .verify(signature)
+            .map_err(|_| anyhow!("the unsubscribe token signature is invalid"))?;
+
+        let (subscriber_id, list_id) = payload.split_at(16);
+        Ok(Self {
+            subscriber_id: Uuid::from_slice(subscriber_id)?,
+            list_id: Uuid::from_slice(list_id)?,
+        })
+    }
+
+    fn payload(&self) -> [u8; PAYLOAD_LENGTH] {
+        [
+            self.subscriber_id.as_bytes().as_ref(),
+            self.list_id.as_bytes().as_ref(),
+        ]
+        .concat()
+        .try_into()
+        .expect("two uuids are 32 bytes long")
+    }
+}
+
+fn mac(secret: &HmacSecret, payload: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.0.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(b"unsubscribe:");
+    mac.update(payload);
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::assert_err;
+    use uuid::Uuid;
+
+    use super::UnsubscribeToken;
+    use crate::startup::HmacSecret;
+
+    fn secret() -> HmacSecret {
+        HmacSecret("a-secret".to_string())
+    }
+
+    #[test]
+    fn a_signed_token_verifies() {
+        let token = UnsubscribeToken::new(Uuid::new_v4(), Uuid::new_v4());
+        let signed = token.sign(&secret());
+        assert_eq!(UnsubscribeToken::verify(&signed, &secret()).unwrap(), token);
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let signed = UnsubscribeToken::new(Uuid::new_v4(), Uuid::new_v4())
+            .sign(&HmacSecret("another-secret".to_string()));
+        assert_err!(UnsubscribeToken::verify(&signed, &secret()));
+    }
+
+    #[test]
+    fn a_tampered_token_is_rejected() {
+        let signed = UnsubscribeToken::new(Uuid::new_v4(), Uuid::new_v4()).sign(&secret());
+        let mut bytes = base64::decode_config(&signed, base64::URL_SAFE_NO_PAD).unwrap();
+        bytes[0] ^= 1;
+        let tampered = base64::encode_config(bytes, base64::URL_SAFE_NO_PAD);
+        assert_err!(UnsubscribeToken::verify(&tampered, &secret()));
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        for token in ["", "not a token", "c2hvcnQ"] {
+            assert_err!(UnsubscribeToken::verify(token, &secret()));
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use actix_http::StatusCode;
+
+use crate::common::{spawn_app, TestApp, TestUser};
+
+async fn export(app: &TestApp, query: &str, accept: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!(
+            "{}/admin/subscribers/export?{}",
+            app.address, query
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .header("Accept", accept)
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+async fn insert_subscribers(app: &TestApp, count: i32, status: &str, day: &str) {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        SELECT
+            md5($3 || $2 || i::TEXT)::UUID,
+            $3 || $2 || i::TEXT || '@example.com',
+            'user ' || i::TEXT,
+            $4::DATE + i * interval '1 second',
+            $2
+        FROM generate_series(1, $1) AS i
+        "#,
+        count,
+        status,
+        day,
+        day.parse::<chrono::NaiveDate>().unwrap()
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("failed to insert subscribers");
+}
+
+#[actix_rt::test]
+async fn exports_require_credentials() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/subscribers/export", app.address))
+        .basic_auth(&app.test_user.username, Some("wrong-password"))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_rt::test]
+async fn viewers_cannot_export_subscribers() {
+    let app = spawn_app().await;
+    let viewer = TestUser::generate();
+    viewer.store(&app.db_pool, &["viewer"]).await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/subscribers/export", app.address))
+        .basic_auth(&viewer.username, Some(&viewer.password))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[actix_rt::test]
+async fn subscribers_are_exported_as_csv() {
+    let app = spawn_app().await;
+    insert_subscribers(&app, 1200, "confirmed", "2021-11-01").await;
+
+    let response = export(&app, "", "text/csv").await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    let body = response.text().await.unwrap();
+    let lines = body.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "id,email,name,status,subscribed_at");
+    assert_eq!(lines.len(), 1201);
+    assert!(lines[1].contains("2021-11-01confirmed1@example.com"));
+    assert!(lines[1200].contains("2021-11-01confirmed1200@example.com"));
+}
+
+#[actix_rt::test]
+async fn subscribers_are_exported_as_ndjson_with_filters() {
+    let app = spawn_app().await;
+    insert_subscribers(&app, 3, "confirmed", "2021-11-01").await;
+    insert_subscribers(&app, 2, "pending", "2021-11-01").await;
+    insert_subscribers(&app, 4, "confirmed", "2021-12-01").await;
+
+    let response = export(
+        &app,
+        "status=confirmed&subscribed_before=2021-11-15T00:00:00Z",
+        "application/x-ndjson",
+    )
+    .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.text().await.unwrap();
+    let subscribers = body
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(subscribers.len(), 3);
+    assert!(subscribers
+        .iter()
+        .all(|s| s["status"] == "confirmed" && s["name"].is_string() && s["id"].is_string()));
+}
+
+#[actix_rt::test]
+async fn an_empty_csv_export_still_has_a_header() {
+    let app = spawn_app().await;
+
+    let response = export(&app, "", "*/*").await;
+
+    assert_eq!(
+        response.text().await.unwrap(),
+        "id,email,name,status,subscribed_at\n"
+    );
+}
+
+#[actix_rt::test]
+async fn unsupported_formats_are_refused() {
+    let app = spawn_app().await;
+
+    let response = export(&app, "", "application/xml").await;
+
+    assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+}
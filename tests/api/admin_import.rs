@@ -0,0 +1,177 @@
+use actix_http::StatusCode;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::common::{create_confirmed_subscriber, spawn_app, TestApp};
+
+async fn import_csv(app: &TestApp, mode: &str, csv: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!(
+            "{}/admin/subscribers/import?list_id={}&mode={}",
+            app.address, app.list_id, mode
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .header("Content-Type", "text/csv")
+        .body(csv.to_string())
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+async fn list_statuses(app: &TestApp) -> Vec<(String, String)> {
+    sqlx::query!(
+        r#"
+        SELECT subscriptions.email, list_subscriptions.status
+        FROM list_subscriptions
+        JOIN subscriptions ON subscriptions.id = list_subscriptions.subscriber_id
+        WHERE list_id = $1
+        ORDER BY subscriptions.email
+        "#,
+        app.list_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| (r.email, r.status))
+    .collect()
+}
+
+#[actix_rt::test]
+async fn imports_require_credentials() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/admin/subscribers/import?list_id={}&mode=confirmed",
+            app.address, app.list_id
+        ))
+        .basic_auth(&app.test_user.username, Some("wrong-password"))
+        .body("email,name\na@example.com,Alice\n")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_rt::test]
+async fn confirmed_imports_report_errors_and_duplicates() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let csv = "email,name,Company\n\
+        alice@example.com,Alice,Acme\n\
+        bob@example.com,Bob,\n\
+        yolo,Nobody,\n\
+        alice@example.com,Alice,Acme\n";
+
+    let response = import_csv(&app, "confirmed", csv).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 2);
+    assert_eq!(report["duplicates"], 1);
+    assert_eq!(report["errors"][0]["line"], 4);
+    assert_eq!(report["errors"][0]["field"], "email");
+    assert_eq!(
+        list_statuses(&app).await,
+        vec![
+            ("alice@example.com".to_string(), "confirmed".to_string()),
+            ("bob@example.com".to_string(), "confirmed".to_string()),
+        ]
+    );
+    let company = sqlx::query!("SELECT value FROM subscriber_attributes WHERE name = 'company'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(company.value, "Acme");
+
+    app.dispatch_outbox_emails().await;
+}
+
+#[actix_rt::test]
+async fn double_opt_in_imports_send_confirmation_emails() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let response = import_csv(
+        &app,
+        "double_opt_in",
+        "email,name\nalice@example.com,Alice\nbob@example.com,Bob\n",
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    app.dispatch_outbox_emails().await;
+
+    assert_eq!(
+        list_statuses(&app).await,
+        vec![
+            ("alice@example.com".to_string(), "pending".to_string()),
+            ("bob@example.com".to_string(), "pending".to_string()),
+        ]
+    );
+    let requests = app.email_server.received_requests().await.unwrap();
+    let links = app.get_confirmation_links(&requests[0]);
+    app.confirm_subscription(&links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+#[actix_rt::test]
+async fn existing_subscribers_are_counted_as_duplicates() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let response = import_csv(
+        &app,
+        "double_opt_in",
+        "email,name\njoseph@google.com,Joseph\n",
+    )
+    .await;
+
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 0);
+    assert_eq!(report["duplicates"], 1);
+    assert_eq!(
+        list_statuses(&app).await,
+        vec![("joseph@google.com".to_string(), "confirmed".to_string())]
+    );
+}
+
+#[actix_rt::test]
+async fn uploads_without_the_required_columns_are_rejected() {
+    let app = spawn_app().await;
+
+    for csv in ["name\nAlice\n", "email\na@example.com\n", ""] {
+        let response = import_csv(&app, "confirmed", csv).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{:?}", csv);
+    }
+}
+
+#[actix_rt::test]
+async fn large_files_are_imported_in_batches() {
+    let app = spawn_app().await;
+    let mut csv = "email,name\n".to_string();
+    for i in 0..1200 {
+        csv.push_str(&format!("user{}@example.com,User {}\n", i, i));
+    }
+
+    let response = import_csv(&app, "confirmed", &csv).await;
+
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 1200);
+    assert_eq!(list_statuses(&app).await.len(), 1200);
+}
@@ -0,0 +1,241 @@
+use actix_http::StatusCode;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use uuid::Uuid;
+
+use crate::common::{create_unconfirmed_subscriber, spawn_app, TestApp};
+
+async fn insert_subscriber(
+    app: &TestApp,
+    email: &str,
+    status: &str,
+    subscribed_at: DateTime<Utc>,
+) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, 'joseph', $3, $4)",
+        id,
+        email,
+        subscribed_at,
+        status
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("failed to insert subscriber");
+    sqlx::query!(
+        "INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+        VALUES ($1, $2, $3, $4)",
+        app.list_id,
+        id,
+        status,
+        subscribed_at
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("failed to insert list subscription");
+    id
+}
+
+fn emails(body: &serde_json::Value) -> Vec<String> {
+    body["subscribers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["email"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[actix_rt::test]
+async fn admin_endpoints_require_credentials() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/subscribers", app.address))
+        .basic_auth(&app.test_user.username, Some("wrong-password"))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        r#"Basic realm="admin""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[actix_rt::test]
+async fn subscribers_are_listed_page_by_page() {
+    let app = spawn_app().await;
+    let start = Utc.ymd(2021, 11, 1).and_hms(0, 0, 0);
+    for i in 0..5 {
+        insert_subscriber(
+            &app,
+            &format!("user{}@example.com", i),
+            "confirmed",
+            start + Duration::minutes(i),
+        )
+        .await;
+    }
+
+    let mut seen = Vec::new();
+    let mut path = "/admin/subscribers?limit=2".to_string();
+    loop {
+        let response = app.get_admin(&path).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = response.json().await.unwrap();
+        seen.extend(emails(&body));
+        match body["next_cursor"].as_str() {
+            Some(cursor) => path = format!("/admin/subscribers?limit=2&cursor={}", cursor),
+            None => break,
+        }
+    }
+
+    let expected: Vec<_> = (0..5).map(|i| format!("user{}@example.com", i)).collect();
+    assert_eq!(seen, expected);
+}
+
+#[actix_rt::test]
+async fn subscribers_can_be_filtered() {
+    let app = spawn_app().await;
+    let start = Utc.ymd(2021, 11, 1).and_hms(0, 0, 0);
+    insert_subscriber(&app, "alice@example.com", "confirmed", start).await;
+    insert_subscriber(
+        &app,
+        "bob@example.com",
+        "pending",
+        start + Duration::days(1),
+    )
+    .await;
+    insert_subscriber(
+        &app,
+        "al_ice@other.com",
+        "confirmed",
+        start + Duration::days(2),
+    )
+    .await;
+
+    let test_cases = [
+        ("status=pending", vec!["bob@example.com"]),
+        ("email=ALICE", vec!["alice@example.com"]),
+        ("email=_", vec!["al_ice@other.com"]),
+        (
+            "subscribed_after=2021-11-02T00:00:00Z",
+            vec!["bob@example.com", "al_ice@other.com"],
+        ),
+        (
+            "status=confirmed&subscribed_before=2021-11-02T00:00:00Z",
+            vec!["alice@example.com"],
+        ),
+    ];
+
+    for (query, expected) in test_cases {
+        let response = app
+            .get_admin(&format!("/admin/subscribers?{}", query))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK, "{}", query);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(emails(&body), expected, "{}", query);
+    }
+}
+
+#[actix_rt::test]
+async fn invalid_list_parameters_are_rejected() {
+    let app = spawn_app().await;
+
+    for query in ["status=bogus", "limit=0", "cursor=garbage"] {
+        let response = app
+            .get_admin(&format!("/admin/subscribers?{}", query))
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", query);
+    }
+}
+
+#[actix_rt::test]
+async fn a_single_subscriber_can_be_fetched_with_their_lists() {
+    let app = spawn_app().await;
+    let id = insert_subscriber(&app, "alice@example.com", "pending", Utc::now()).await;
+
+    let response = app.get_admin(&format!("/admin/subscribers/{}", id)).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["email"], "alice@example.com");
+    assert_eq!(body["lists"][0]["name"], "test list");
+    assert_eq!(body["lists"][0]["status"], "pending");
+
+    let response = app
+        .get_admin(&format!("/admin/subscribers/{}", Uuid::new_v4()))
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_rt::test]
+async fn the_status_of_a_subscriber_can_be_changed() {
+    let app = spawn_app().await;
+    let id = insert_subscriber(&app, "alice@example.com", "pending", Utc::now()).await;
+
+    let response = app
+        .put_admin(
+            &format!("/admin/subscribers/{}/status", id),
+            serde_json::json!({ "status": "confirmed", "list_id": app.list_id }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body: serde_json::Value = app
+        .get_admin(&format!("/admin/subscribers/{}", id))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["status"], "confirmed");
+    assert_eq!(body["lists"][0]["status"], "confirmed");
+
+    let response = app
+        .put_admin(
+            &format!("/admin/subscribers/{}/status", id),
+            serde_json::json!({ "status": "bogus" }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = app
+        .put_admin(
+            &format!("/admin/subscribers/{}/status", Uuid::new_v4()),
+            serde_json::json!({ "status": "confirmed" }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_rt::test]
+async fn deleting_a_subscriber_removes_their_tokens() {
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    let id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+
+    let response = app
+        .delete_admin(&format!("/admin/subscribers/{}", id))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let remaining = sqlx::query!(
+        r#"SELECT
+            (SELECT count(*) FROM subscriptions) as "subscribers!",
+            (SELECT count(*) FROM subscription_tokens) as "tokens!""#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(remaining.subscribers, 0);
+    assert_eq!(remaining.tokens, 0);
+
+    let response = app
+        .delete_admin(&format!("/admin/subscribers/{}", id))
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
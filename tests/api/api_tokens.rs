@@ -0,0 +1,207 @@
+use reqwest::StatusCode;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::common::{create_confirmed_subscriber, spawn_app, TestApp};
+
+async fn get_with_token(app: &TestApp, path: &str, token: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}{}", app.address, path))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[actix_rt::test]
+async fn created_tokens_are_shown_once_and_stored_hashed() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_api_token(serde_json::json!({
+            "name": "release notes",
+            "scopes": ["newsletter:publish", "subscribers:read", "newsletter:publish"],
+            "expires_in_days": 7
+        }))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let created: serde_json::Value = response.json().await.unwrap();
+    let token = created["token"].as_str().unwrap();
+    assert!(token.starts_with("z2p_"));
+    assert_eq!(
+        created["scopes"],
+        serde_json::json!(["newsletter:publish", "subscribers:read"])
+    );
+
+    let stored = sqlx::query!("SELECT token_hash FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(stored.token_hash, token);
+
+    let listed: serde_json::Value = app.get_admin("/admin/tokens").await.json().await.unwrap();
+    let listed = listed.as_array().unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0]["name"], "release notes");
+    assert_eq!(listed[0]["last_used_at"], serde_json::Value::Null);
+    assert!(listed[0].get("token").is_none());
+}
+
+#[actix_rt::test]
+async fn invalid_token_requests_are_rejected() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({ "name": "ci", "scopes": [] }),
+            "no scopes",
+        ),
+        (
+            serde_json::json!({ "name": "ci", "scopes": ["everything"] }),
+            "unknown scope",
+        ),
+        (
+            serde_json::json!({ "name": " ", "scopes": ["subscribers:read"] }),
+            "blank name",
+        ),
+        (
+            serde_json::json!({ "name": "ci", "scopes": ["subscribers:read"], "expires_in_days": 0 }),
+            "already expired",
+        ),
+        (
+            serde_json::json!({ "name": "ci", "scopes": ["subscribers:read"], "expires_in_days": 366 }),
+            "too long lived",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.post_api_token(body).await;
+
+        assert_eq!(
+            response.status(),
+            StatusCode::BAD_REQUEST,
+            "the API did not reject a token with {}",
+            description
+        );
+    }
+}
+
+#[actix_rt::test]
+async fn tokens_authenticate_routes_within_their_scopes() {
+    let app = spawn_app().await;
+    let token = app.create_api_token(&["subscribers:read"]).await;
+
+    let response = get_with_token(&app, "/admin/subscribers", &token).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let last_used = sqlx::query!("SELECT last_used_at FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(last_used.last_used_at.is_some());
+}
+
+#[actix_rt::test]
+async fn tokens_without_the_required_scope_are_forbidden() {
+    let app = spawn_app().await;
+    let token = app.create_api_token(&["newsletter:publish"]).await;
+
+    let response = get_with_token(&app, "/admin/subscribers", &token).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = reqwest::Client::new()
+        .delete(format!(
+            "{}/admin/subscribers/{}",
+            app.address,
+            Uuid::new_v4()
+        ))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[actix_rt::test]
+async fn tokens_can_publish_newsletters() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = app.create_api_token(&["newsletter:publish"]).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletter", app.address))
+        .bearer_auth(&token)
+        .json(&serde_json::json!({
+            "list_id": app.list_id,
+            "title": "Release notes",
+            "content": {
+                "text": "plain text body",
+                "html": "<b>html body</b>"
+            }
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+}
+
+#[actix_rt::test]
+async fn expired_and_unknown_tokens_are_rejected() {
+    let app = spawn_app().await;
+    let token = app.create_api_token(&["subscribers:read"]).await;
+    sqlx::query!("UPDATE api_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = get_with_token(&app, "/admin/subscribers", &token).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = get_with_token(&app, "/admin/subscribers", "z2p_not-a-token").await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_rt::test]
+async fn revoked_tokens_stop_working() {
+    let app = spawn_app().await;
+    let token = app.create_api_token(&["subscribers:read"]).await;
+    let listed: serde_json::Value = app.get_admin("/admin/tokens").await.json().await.unwrap();
+    let token_id = listed[0]["token_id"].as_str().unwrap();
+
+    let response = app
+        .delete_admin(&format!("/admin/tokens/{}", token_id))
+        .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = get_with_token(&app, "/admin/subscribers", &token).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = app
+        .delete_admin(&format!("/admin/tokens/{}", token_id))
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_rt::test]
+async fn tokens_cannot_manage_tokens() {
+    let app = spawn_app().await;
+    let token = app
+        .create_api_token(&[
+            "newsletter:publish",
+            "subscribers:read",
+            "subscribers:write",
+        ])
+        .await;
+
+    let response = get_with_token(&app, "/admin/tokens", &token).await;
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
@@ -3,10 +3,18 @@ use once_cell::sync::Lazy;
 use reqwest::Url;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
 use zero2prod::{
     configuration::{get_config, DatabaseSettings},
-    startup::{get_connection_pool, Application},
+    delivery::ExecutionOutcome,
+    email_client::EmailClient,
+    email_outbox::try_dispatch_email,
+    issue_delivery_worker::try_execute_task,
+    newsletter_scheduler::{try_release_issue, ReleaseOutcome},
+    startup::{get_connection_pool, Application, HmacSecret},
     telemetry::{get_subscriber, init_subscriber},
 };
 
@@ -26,9 +34,15 @@ static TRACING: Lazy<()> = Lazy::new(|| {
 pub struct TestApp {
     pub address: String,
     pub db_pool: PgPool,
+    pub db_settings: DatabaseSettings,
     pub email_server: MockServer,
+    pub email_client: EmailClient,
+    pub base_url: String,
+    pub hmac_secret: HmacSecret,
+    pub list_id: Uuid,
     pub port: u16,
     pub test_user: TestUser,
+    pub api_client: reqwest::Client,
 }
 
 pub struct ConfirmationLinks {
@@ -47,6 +61,189 @@ impl TestApp {
             .expect("failed to execute request")
     }
 
+    pub async fn post_subscriptions_json(&self, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_confirmation(&self, subscription_token: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions/confirm", self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(format!("subscription_token={}", subscription_token))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    /// Opens the confirmation page behind `link` and submits its form.
+    pub async fn confirm_subscription(&self, link: &Url) -> reqwest::Response {
+        reqwest::get(link.clone())
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+        let (_, subscription_token) = link
+            .query_pairs()
+            .find(|(key, _)| key == "subscription_token")
+            .unwrap();
+        self.post_confirmation(&subscription_token).await
+    }
+
+    pub async fn create_list(&self, name: &str) -> Uuid {
+        let list_id = Uuid::new_v4();
+        sqlx::query!(
+            "INSERT INTO lists (list_id, name, created_at) VALUES ($1, $2, now())",
+            list_id,
+            name
+        )
+        .execute(&self.db_pool)
+        .await
+        .expect("failed to create list");
+        list_id
+    }
+
+    pub async fn get_login_form(&self) -> String {
+        self.api_client
+            .get(format!("{}/login", self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_login(&self, username: &str, password: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/login", self.address))
+            .form(&[("username", username), ("password", password)])
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn log_in_as_test_user(&self) {
+        let response = self
+            .post_login(&self.test_user.username, &self.test_user.password)
+            .await;
+        assert_is_redirect_to(&response, "/admin/dashboard");
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/logout", self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_change_password(
+        &self,
+        current_password: &str,
+        new_password: &str,
+        new_password_check: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/password", self.address))
+            .form(&[
+                ("current_password", current_password),
+                ("new_password", new_password),
+                ("new_password_check", new_password_check),
+            ])
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_forgot_password(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/password/forgot", self.address))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_reset_password(
+        &self,
+        token: &str,
+        new_password: &str,
+        new_password_check: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/password/reset", self.address))
+            .form(&[
+                ("token", token),
+                ("new_password", new_password),
+                ("new_password_check", new_password_check),
+            ])
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_session_admin(&self, path: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}{}", self.address, path))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_api_token(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/tokens", self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    /// Creates an API token for the test user and returns its secret value.
+    pub async fn create_api_token(&self, scopes: &[&str]) -> String {
+        let response = self
+            .post_api_token(serde_json::json!({ "name": "ci", "scopes": scopes }))
+            .await;
+        assert_eq!(response.status().as_u16(), 201);
+        let body: serde_json::Value = response.json().await.unwrap();
+        body["token"].as_str().unwrap().to_owned()
+    }
+
+    pub async fn get_admin(&self, path: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}{}", &self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn put_admin(&self, path: &str, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!("{}{}", &self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn delete_admin(&self, path: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!("{}{}", &self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    #[allow(clippy::needless_borrows_for_generic_args)]
     pub async fn post_newsletter(&self, body: serde_json::Value) -> reqwest::Response {
         reqwest::Client::new()
             .post(&format!("{}/newsletter", &self.address))
@@ -57,6 +254,137 @@ impl TestApp {
             .expect("failed to execute request")
     }
 
+    pub async fn post_newsletter_with_idempotency_key(
+        &self,
+        body: serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletter", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
+            .json(&body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_scheduled_newsletters(&self) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/newsletter/scheduled", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn put_scheduled_newsletter(
+        &self,
+        newsletter_issue_id: &str,
+        send_at: chrono::DateTime<chrono::Utc>,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!(
+                "{}/newsletter/scheduled/{}",
+                &self.address, newsletter_issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&serde_json::json!({ "send_at": send_at }))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn delete_scheduled_newsletter(
+        &self,
+        newsletter_issue_id: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!(
+                "{}/newsletter/scheduled/{}",
+                &self.address, newsletter_issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_dead_letters(&self) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/newsletter/dead_letters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_replay_dead_letters(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletter/dead_letters/replay", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn dispatch_all_pending_emails(&self) {
+        self.release_due_issues().await;
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                &self.email_client,
+                &self.base_url,
+                &self.hmac_secret,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn dispatch_outbox_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_dispatch_email(&self.db_pool, &self.email_client)
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn release_due_issues(&self) {
+        loop {
+            if let ReleaseOutcome::NothingDue = try_release_issue(&self.db_pool).await.unwrap() {
+                break;
+            }
+        }
+    }
+
+    pub fn get_unsubscribe_link(&self, email_request: &wiremock::Request) -> reqwest::Url {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let header = body["Headers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|header| header["Name"] == "List-Unsubscribe")
+            .unwrap();
+        let link = header["Value"]
+            .as_str()
+            .unwrap()
+            .trim_start_matches('<')
+            .trim_end_matches('>');
+        let mut url = Url::parse(link).unwrap();
+        url.set_port(Some(self.port)).unwrap();
+        url
+    }
+
+    #[allow(clippy::filter_next, clippy::needless_borrow)]
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
 
@@ -106,17 +434,41 @@ pub async fn spawn_app() -> TestApp {
 
     let test_user = TestUser::generate();
 
-    test_user.store(&db_pool).await;
+    test_user.store(&db_pool, &["admin"]).await;
+
+    let list_id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO lists (list_id, name, created_at) VALUES ($1, 'test list', now())",
+        list_id
+    )
+    .execute(&db_pool)
+    .await
+    .expect("failed to create test list");
 
     TestApp {
         address,
         db_pool,
+        db_settings: config.database,
         email_server,
+        email_client: config.email_client.client(),
+        base_url: config.application.base_url,
+        hmac_secret: HmacSecret(config.application.hmac_secret),
+        list_id,
         port,
         test_user,
+        api_client: reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .cookie_store(true)
+            .build()
+            .unwrap(),
     }
 }
 
+pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers()["Location"], location);
+}
+
 async fn configure_database(config: &DatabaseSettings) -> PgPool {
     let mut connection = PgConnection::connect_with(&config.without_db())
         .await
@@ -139,6 +491,7 @@ async fn configure_database(config: &DatabaseSettings) -> PgPool {
 pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
+    pub email: String,
     pub password: String,
 }
 
@@ -147,25 +500,75 @@ impl TestUser {
         Self {
             user_id: Uuid::new_v4(),
             username: Uuid::new_v4().to_string(),
+            email: format!("{}@example.com", Uuid::new_v4()),
             password: Uuid::new_v4().to_string(),
         }
     }
 
-    async fn store(&self, pool: &PgPool) {
+    pub async fn store(&self, pool: &PgPool, roles: &[&str]) {
         let salt = SaltString::generate(&mut rand::thread_rng());
         let password_hash = Argon2::default()
             .hash_password(self.password.as_bytes(), &salt)
             .unwrap()
             .to_string();
         sqlx::query!(
-            "INSERT INTO users (user_id, username, password_hash)
-            VALUES ($1, $2, $3)",
+            "INSERT INTO users (user_id, username, email, password_hash)
+            VALUES ($1, $2, $3, $4)",
             self.user_id,
             self.username,
+            self.email,
             password_hash
         )
         .execute(pool)
         .await
         .expect("failed to create test users");
+        for role in roles {
+            sqlx::query!(
+                "INSERT INTO user_roles (user_id, role) VALUES ($1, $2)",
+                self.user_id,
+                role
+            )
+            .execute(pool)
+            .await
+            .expect("failed to grant test user a role");
+        }
     }
 }
+
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let body = format!(
+        "name=joseph&email=joseph@google.com&list_id={}",
+        app.list_id
+    );
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_outbox_emails().await;
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+
+    app.get_confirmation_links(email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_link = create_unconfirmed_subscriber(app).await;
+    app.confirm_subscription(&confirmation_link.html)
+        .await
+        .error_for_status()
+        .unwrap();
+}
@@ -0,0 +1,316 @@
+use std::time::{Duration, Instant};
+
+use actix_http::StatusCode;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::{
+    configuration::DatabaseSettings, issue_delivery_worker::IssueDeliveryWorker,
+    startup::get_connection_pool,
+};
+
+use crate::common::{create_confirmed_subscriber, spawn_app, TestApp};
+
+async fn publish_newsletter(app: &TestApp) -> String {
+    let response = app
+        .post_newsletter(serde_json::json!({
+            "list_id": app.list_id,
+            "title": "newsletter title",
+            "content": {
+                "text": "plain text body",
+                "html": "<b>html body</b>"
+            }
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["newsletter_issue_id"].as_str().unwrap().to_owned()
+}
+
+struct DeliveryRow {
+    status: String,
+    n_attempts: i16,
+    last_error: Option<String>,
+}
+
+async fn delivery_row(app: &TestApp) -> DeliveryRow {
+    sqlx::query_as!(
+        DeliveryRow,
+        "SELECT status, n_attempts, last_error FROM issue_delivery_queue"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("failed to query the delivery queue")
+}
+
+#[actix_rt::test]
+async fn successful_deliveries_are_recorded_as_delivered() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let delivery = delivery_row(&app).await;
+    assert_eq!(delivery.status, "delivered");
+    assert_eq!(delivery.n_attempts, 1);
+    assert_eq!(delivery.last_error, None);
+}
+
+#[actix_rt::test]
+async fn transient_failures_are_scheduled_for_retry() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let delivery = delivery_row(&app).await;
+    assert_eq!(delivery.status, "pending");
+    assert_eq!(delivery.n_attempts, 1);
+    assert!(delivery.last_error.is_some());
+
+    let retry_is_delayed =
+        sqlx::query!(r#"SELECT execute_after > now() as "delayed!" FROM issue_delivery_queue"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert!(retry_is_delayed.delayed);
+}
+
+#[actix_rt::test]
+async fn abandoned_deliveries_are_claimed_again_once_their_lease_expires() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    app.release_due_issues().await;
+    // A worker claimed the task and died before recording the outcome.
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET status = 'in_flight', n_attempts = 1, execute_after = now() + interval '1 minute'
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(delivery_row(&app).await.status, "in_flight");
+
+    sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let delivery = delivery_row(&app).await;
+    assert_eq!(delivery.status, "delivered");
+    assert_eq!(delivery.n_attempts, 2);
+}
+
+#[actix_rt::test]
+async fn permanent_failures_are_dead_lettered_immediately() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let delivery = delivery_row(&app).await;
+    assert_eq!(delivery.status, "dead_lettered");
+    assert_eq!(delivery.n_attempts, 1);
+}
+
+#[actix_rt::test]
+async fn deliveries_are_dead_lettered_after_too_many_attempts() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+    app.release_due_issues().await;
+    sqlx::query!("UPDATE issue_delivery_queue SET n_attempts = 4")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let delivery = delivery_row(&app).await;
+    assert_eq!(delivery.status, "dead_lettered");
+    assert_eq!(delivery.n_attempts, 5);
+}
+
+#[actix_rt::test]
+async fn dead_letters_can_be_listed_and_replayed() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let failing_mock = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let issue_id = publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+    drop(failing_mock);
+
+    let response = app.get_dead_letters().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let dead_letters: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(dead_letters.as_array().unwrap().len(), 1);
+    assert_eq!(dead_letters[0]["newsletter_issue_id"], issue_id.as_str());
+    assert_eq!(dead_letters[0]["subscriber_email"], "joseph@google.com");
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_replay_dead_letters(serde_json::json!({ "newsletter_issue_id": issue_id }))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["replayed"], 1);
+
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(delivery_row(&app).await.status, "delivered");
+}
+
+#[actix_rt::test]
+async fn replaying_an_issue_without_dead_letters_returns_404() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_replay_dead_letters(serde_json::json!({ "newsletter_issue_id": Uuid::new_v4() }))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_rt::test]
+async fn concurrent_sends_do_not_hold_connections() {
+    let app = spawn_app().await;
+    let max_concurrent_sends = 12;
+    for n in 0..max_concurrent_sends {
+        let id = Uuid::new_v4();
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+            VALUES ($1, $2, 'joseph', now(), 'confirmed')
+            "#,
+            id,
+            format!("subscriber-{}@example.com", n)
+        )
+        .execute(&app.db_pool)
+        .await
+        .expect("failed to insert subscriber");
+        sqlx::query!(
+            r#"
+            INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+            VALUES ($1, $2, 'confirmed', now())
+            "#,
+            app.list_id,
+            id
+        )
+        .execute(&app.db_pool)
+        .await
+        .expect("failed to subscribe to list");
+    }
+
+    let send_time = Duration::from_secs(3);
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(send_time))
+        .expect(max_concurrent_sends as u64)
+        .mount(&app.email_server)
+        .await;
+    publish_newsletter(&app).await;
+    app.release_due_issues().await;
+
+    // Far fewer connections than sends.
+    let pool = get_connection_pool(&DatabaseSettings {
+        max_connections: 2,
+        ..app.db_settings.clone()
+    })
+    .await
+    .expect("failed to connect to db");
+    let started = Instant::now();
+    let worker = actix_rt::spawn(
+        IssueDeliveryWorker::new(
+            pool.clone(),
+            app.email_client.clone(),
+            app.base_url.clone(),
+            app.hmac_secret.clone(),
+            max_concurrent_sends,
+        )
+        .run_until_stopped(),
+    );
+
+    // Every send is now waiting on the provider, none of them on a connection.
+    actix_rt::time::sleep(Duration::from_secs(1)).await;
+    sqlx::query!("SELECT 1 AS one")
+        .fetch_one(&pool)
+        .await
+        .expect("the pool was exhausted by the sends");
+
+    loop {
+        let pending = sqlx::query!(
+            r#"SELECT count(*) AS "count!" FROM issue_delivery_queue WHERE status <> 'delivered'"#
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .count;
+        if pending == 0 {
+            break;
+        }
+        assert!(
+            started.elapsed() < send_time * 3,
+            "the sends did not run concurrently"
+        );
+        actix_rt::time::sleep(Duration::from_millis(100)).await;
+    }
+    worker.abort();
+}
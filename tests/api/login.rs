@@ -0,0 +1,127 @@
+use reqwest::StatusCode;
+
+use crate::common::{assert_is_redirect_to, spawn_app};
+
+#[actix_rt::test]
+async fn failed_logins_show_a_flash_message_once() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_login(&app.test_user.username, "wrong-password")
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html = app.get_login_form().await;
+    assert!(html.contains("<i>Authentication failed</i>"));
+
+    let html = app.get_login_form().await;
+    assert!(!html.contains("Authentication failed"));
+}
+
+#[actix_rt::test]
+async fn unknown_users_cannot_log_in() {
+    let app = spawn_app().await;
+
+    let response = app.post_login("nobody", "some-password").await;
+
+    assert_is_redirect_to(&response, "/login");
+    let sessions = sqlx::query!("SELECT COUNT(*) AS count FROM sessions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(sessions.count, Some(0));
+}
+
+#[actix_rt::test]
+async fn logged_in_users_reach_the_dashboard() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_login(&app.test_user.username, &app.test_user.password)
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let cookie = response.headers()["Set-Cookie"].to_str().unwrap();
+    assert!(cookie.contains("HttpOnly"));
+
+    let response = app.get_session_admin("/admin/dashboard").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = response.text().await.unwrap();
+    assert!(html.contains(&format!("Welcome, {}!", app.test_user.username)));
+}
+
+#[actix_rt::test]
+async fn the_admin_api_accepts_a_login_session() {
+    let app = spawn_app().await;
+    app.log_in_as_test_user().await;
+
+    let response = app.get_session_admin("/admin/subscribers").await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn anonymous_visitors_are_sent_to_the_login_form() {
+    let app = spawn_app().await;
+
+    let response = app.get_session_admin("/admin/dashboard").await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_rt::test]
+async fn logging_out_ends_the_session() {
+    let app = spawn_app().await;
+    app.log_in_as_test_user().await;
+
+    let response = app.post_logout().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html = app.get_login_form().await;
+    assert!(html.contains("<i>You have successfully logged out.</i>"));
+    let response = app.get_session_admin("/admin/dashboard").await;
+    assert_is_redirect_to(&response, "/login");
+    let sessions = sqlx::query!("SELECT COUNT(*) AS count FROM sessions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(sessions.count, Some(0));
+}
+
+#[actix_rt::test]
+async fn tampered_session_cookies_are_rejected() {
+    let app = spawn_app().await;
+    let response = app
+        .post_login(&app.test_user.username, &app.test_user.password)
+        .await;
+    let cookie = response.headers()["Set-Cookie"].to_str().unwrap();
+    let value = cookie.split(';').next().unwrap();
+    let (session, signature) = value.rsplit_once('.').unwrap();
+    let last = if session.ends_with('0') { '1' } else { '0' };
+    let tampered = format!("{}{}.{}", &session[..session.len() - 1], last, signature);
+
+    let response = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(format!("{}/admin/dashboard", app.address))
+        .header("Cookie", tampered)
+        .send()
+        .await
+        .unwrap();
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_rt::test]
+async fn expired_sessions_are_rejected() {
+    let app = spawn_app().await;
+    app.log_in_as_test_user().await;
+    sqlx::query!("UPDATE sessions SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.get_session_admin("/admin/dashboard").await;
+
+    assert_is_redirect_to(&response, "/login");
+}
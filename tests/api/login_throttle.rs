@@ -0,0 +1,162 @@
+use reqwest::StatusCode;
+
+use crate::common::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn seed_failures(app: &TestApp, kind: &str, subject: &str, failures: i32) {
+    sqlx::query!(
+        r#"
+        INSERT INTO failed_logins (kind, subject, failures, last_failure_at)
+        VALUES ($1, $2, $3, now())
+        "#,
+        kind,
+        subject,
+        failures
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+fn retry_after(response: &reqwest::Response) -> u64 {
+    response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[actix_rt::test]
+async fn repeated_failures_delay_further_attempts() {
+    let app = spawn_app().await;
+    for _ in 0..4 {
+        let response = app
+            .post_login(&app.test_user.username, "wrong-password")
+            .await;
+        assert_is_redirect_to(&response, "/login");
+    }
+
+    let response = app
+        .post_login(&app.test_user.username, &app.test_user.password)
+        .await;
+
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(retry_after(&response), 1);
+}
+
+#[actix_rt::test]
+async fn concurrent_failures_are_throttled_like_sequential_ones() {
+    let app = spawn_app().await;
+    seed_failures(&app, "username", &app.test_user.username, 3).await;
+
+    let attempts = (0..10).map(|_| app.post_login(&app.test_user.username, "wrong-password"));
+    let responses = futures::future::join_all(attempts).await;
+
+    let throttled = responses
+        .iter()
+        .filter(|response| response.status() == StatusCode::TOO_MANY_REQUESTS)
+        .count();
+    assert_eq!(throttled, 9);
+    let failures = sqlx::query!("SELECT failures FROM failed_logins WHERE kind = 'username'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .failures;
+    assert_eq!(failures, 4);
+}
+
+#[actix_rt::test]
+async fn too_many_failures_lock_the_username_out() {
+    let app = spawn_app().await;
+    seed_failures(&app, "username", &app.test_user.username, 9).await;
+
+    let response = app
+        .post_login(&app.test_user.username, "wrong-password")
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app
+        .post_login(&app.test_user.username, &app.test_user.password)
+        .await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!((890..=900).contains(&retry_after(&response)));
+
+    let response = app.get_admin("/admin/subscribers").await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key("Retry-After"));
+}
+
+#[actix_rt::test]
+async fn too_many_failures_from_an_address_lock_it_out() {
+    let app = spawn_app().await;
+    seed_failures(&app, "ip", "127.0.0.1", 49).await;
+
+    let response = app.post_login("someone-else", "wrong-password").await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app
+        .post_login(&app.test_user.username, &app.test_user.password)
+        .await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+async fn post_bad_login_forwarded_for(app: &TestApp, forwarded_for: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/login", app.address))
+        .header("X-Forwarded-For", forwarded_for)
+        .form(&[
+            ("username", app.test_user.username.as_str()),
+            ("password", "wrong-password"),
+        ])
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+#[actix_rt::test]
+async fn forged_forwarded_addresses_do_not_escape_the_address_lockout() {
+    let app = spawn_app().await;
+    seed_failures(&app, "ip", "127.0.0.1", 49).await;
+
+    let response = post_bad_login_forwarded_for(&app, "198.51.100.1").await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = post_bad_login_forwarded_for(&app, "198.51.100.2").await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_rt::test]
+async fn a_successful_login_clears_the_username_failures() {
+    let app = spawn_app().await;
+    seed_failures(&app, "username", &app.test_user.username, 3).await;
+
+    app.log_in_as_test_user().await;
+    let response = app
+        .post_login(&app.test_user.username, "wrong-password")
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    let failures =
+        sqlx::query!("SELECT failures, blocked_until FROM failed_logins WHERE kind = 'username'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(failures.failures, 1);
+    assert!(failures.blocked_until.is_none());
+}
+
+#[actix_rt::test]
+async fn old_failures_are_forgotten() {
+    let app = spawn_app().await;
+    seed_failures(&app, "username", &app.test_user.username, 9).await;
+    sqlx::query!("UPDATE failed_logins SET last_failure_at = now() - interval '1 hour'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .post_login(&app.test_user.username, "wrong-password")
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    app.log_in_as_test_user().await;
+}
@@ -1,4 +1,16 @@
+mod admin_export;
+mod admin_import;
+mod admin_subscribers;
+mod api_tokens;
 mod common;
+mod deliveries;
 mod health_check;
+mod login;
+mod login_throttle;
 mod newsletter;
+mod password;
+mod roles;
+mod scheduled_newsletters;
+mod subscription_cleanup;
 mod subscriptions;
+mod unsubscribe;
@@ -5,7 +5,7 @@ use wiremock::{
     Mock, ResponseTemplate,
 };
 
-use crate::common::{spawn_app, ConfirmationLinks, TestApp};
+use crate::common::{create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app};
 
 #[actix_rt::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
@@ -19,6 +19,7 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
         .await;
 
     let newsletter_request_body = serde_json::json!({
+        "list_id": app.list_id,
         "title": "newsletter title",
         "content": {
             "text": "plain text body",
@@ -28,7 +29,8 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
 
     let response = app.post_newsletter(newsletter_request_body).await;
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    app.dispatch_all_pending_emails().await;
 }
 
 #[actix_rt::test]
@@ -44,6 +46,7 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
         .await;
 
     let newsletter_request_body = serde_json::json!({
+        "list_id": app.list_id,
         "title": "newsletter title",
         "content": {
             "text": "plain text body",
@@ -53,7 +56,443 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
 
     let response = app.post_newsletter(newsletter_request_body).await;
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[actix_rt::test]
+async fn publishing_returns_the_issue_id_and_queues_a_delivery_task() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let newsletter_request_body = serde_json::json!({
+        "list_id": app.list_id,
+        "title": "newsletter title",
+        "content": {
+            "text": "plain text body",
+            "html": "<b>html body</b>"
+        }
+    });
+
+    let response = app.post_newsletter(newsletter_request_body).await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    let issue_id: Uuid = body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    app.release_due_issues().await;
+
+    let queued = sqlx::query!(
+        "SELECT subscriber_email FROM issue_delivery_queue WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .expect("failed to query the delivery queue");
+    assert_eq!(queued.len(), 1);
+}
+
+#[actix_rt::test]
+async fn markdown_bodies_are_rendered_to_html_and_text() {
+    let app = spawn_app().await;
+
+    let newsletter_request_body = serde_json::json!({
+        "list_id": app.list_id,
+        "title": "newsletter title",
+        "markdown": "Hello **there**, read [the post](https://example.com/post)."
+    });
+
+    let response = app.post_newsletter(newsletter_request_body).await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    let issue_id: Uuid = body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    let issue = sqlx::query!(
+        "SELECT html_content, text_content FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("failed to fetch the newsletter issue");
+
+    assert!(issue.html_content.contains("<strong>there</strong>"));
+    assert_eq!(
+        issue.text_content,
+        "Hello there, read the post [1].\n\n[1] https://example.com/post"
+    );
+}
+
+#[actix_rt::test]
+async fn merge_fields_can_be_used_as_markdown_link_destinations() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "list_id": app.list_id,
+        "title": "newsletter title",
+        "markdown": "Hi {{ name }}, you can [leave]({{unsubscribe_url}}) any time."
+    });
+    let response = app.post_newsletter(newsletter_request_body).await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html = body["HtmlBody"].as_str().unwrap();
+    assert!(html.starts_with("<p>Hi joseph, you can <a href=\""));
+    assert!(html.contains(&format!(
+        "<a href=\"{}/subscriptions/unsubscribe?",
+        app.base_url
+    )));
+    assert!(!html.contains("%7B%7B"));
+    let text = body["TextBody"].as_str().unwrap();
+    assert!(text.contains(&format!("[1] {}/subscriptions/unsubscribe?", app.base_url)));
+}
+
+#[actix_rt::test]
+async fn newsletters_are_only_delivered_to_the_target_list() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let other_list_id = app.create_list("other list").await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "list_id": other_list_id,
+        "title": "newsletter title",
+        "content": {
+            "text": "plain text body",
+            "html": "<b>html body</b>"
+        }
+    });
+
+    let response = app.post_newsletter(newsletter_request_body).await;
+
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[actix_rt::test]
+async fn newsletters_without_a_list_go_to_the_newsletter_list() {
+    let app = spawn_app().await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "newsletter title",
+        "content": {
+            "text": "plain text body",
+            "html": "<b>html body</b>"
+        }
+    });
+
+    let response = app.post_newsletter(newsletter_request_body).await;
+
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let list = sqlx::query!(
+        r#"
+        SELECT lists.name AS "name!" FROM newsletter_issues
+        JOIN lists ON lists.list_id = newsletter_issues.list_id
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("failed to fetch the newsletter issue");
+    assert_eq!(list.name, "newsletter");
+}
+
+#[actix_rt::test]
+async fn newsletters_for_an_unknown_list_are_rejected() {
+    let app = spawn_app().await;
+    let list_id = Uuid::new_v4();
+
+    let newsletter_request_body = serde_json::json!({
+        "list_id": list_id,
+        "title": "newsletter title",
+        "content": {
+            "text": "plain text body",
+            "html": "<b>html body</b>"
+        }
+    });
+
+    let response = app.post_newsletter(newsletter_request_body).await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        response.text().await.unwrap(),
+        format!("unknown list {}", list_id)
+    );
+}
+
+#[actix_rt::test]
+async fn newsletters_are_personalised_for_each_subscriber() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_attributes (subscriber_id, name, value)
+        SELECT id, 'company', 'Acme' FROM subscriptions
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("failed to store subscriber attributes");
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "list_id": app.list_id,
+        "title": "News for {{ name }}",
+        "content": {
+            "text": "Hi {{ name }} from {{ attributes.company }}, this went to {{ email }}",
+            "html": "<p>Hi {{ name }}</p><a href=\"{{ unsubscribe_url }}\">unsubscribe</a>"
+        }
+    });
+    let response = app.post_newsletter(newsletter_request_body).await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "News for joseph");
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("Hi joseph from Acme, this went to joseph@google.com\n\nUnsubscribe: "));
+    let html = body["HtmlBody"].as_str().unwrap();
+    assert!(html.starts_with("<p>Hi joseph</p>"));
+    assert!(html.contains(&format!("{}/subscriptions/unsubscribe?", app.base_url)));
+}
+
+#[actix_rt::test]
+async fn an_interrupted_fan_out_resumes_after_the_saved_cursor() {
+    let app = spawn_app().await;
+    let mut subscriber_ids = [Uuid::new_v4(), Uuid::new_v4()];
+    subscriber_ids.sort();
+    for (id, email) in subscriber_ids
+        .iter()
+        .zip(["first@example.com", "second@example.com"])
+    {
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+            VALUES ($1, $2, 'joseph', now(), 'confirmed')
+            "#,
+            id,
+            email
+        )
+        .execute(&app.db_pool)
+        .await
+        .expect("failed to insert subscriber");
+        sqlx::query!(
+            r#"
+            INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+            VALUES ($1, $2, 'confirmed', now())
+            "#,
+            app.list_id,
+            id
+        )
+        .execute(&app.db_pool)
+        .await
+        .expect("failed to subscribe to list");
+    }
+
+    let response = app
+        .post_newsletter(serde_json::json!({
+            "list_id": app.list_id,
+            "title": "newsletter title",
+            "content": {
+                "text": "plain text body",
+                "html": "<b>html body</b>"
+            }
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    sqlx::query!(
+        "UPDATE newsletter_issues SET delivery_cursor = $1",
+        subscriber_ids[0]
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("failed to save the delivery cursor");
+    app.release_due_issues().await;
+
+    let queued = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("failed to query the delivery queue");
+    assert_eq!(queued.len(), 1);
+    assert_eq!(queued[0].subscriber_email, "second@example.com");
+
+    let issue = sqlx::query!("SELECT status FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("failed to query newsletter issues");
+    assert_eq!(issue.status, "published");
+}
+
+#[actix_rt::test]
+async fn newsletter_creation_is_idempotent() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "list_id": app.list_id,
+        "title": "newsletter title",
+        "content": {
+            "text": "plain text body",
+            "html": "<b>html body</b>"
+        }
+    });
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    let first_response = app
+        .post_newsletter_with_idempotency_key(newsletter_request_body.clone(), &idempotency_key)
+        .await;
+    assert_eq!(first_response.status(), StatusCode::ACCEPTED);
+    let first_body = first_response.text().await.unwrap();
+
+    let second_response = app
+        .post_newsletter_with_idempotency_key(newsletter_request_body, &idempotency_key)
+        .await;
+    assert_eq!(second_response.status(), StatusCode::ACCEPTED);
+    assert_eq!(
+        second_response.headers()["Content-Type"],
+        "application/json"
+    );
+    assert_eq!(second_response.text().await.unwrap(), first_body);
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[actix_rt::test]
+async fn concurrent_newsletter_submissions_are_handled_gracefully() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "list_id": app.list_id,
+        "title": "newsletter title",
+        "content": {
+            "text": "plain text body",
+            "html": "<b>html body</b>"
+        }
+    });
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    let (first_response, second_response) = tokio::join!(
+        app.post_newsletter_with_idempotency_key(newsletter_request_body.clone(), &idempotency_key),
+        app.post_newsletter_with_idempotency_key(newsletter_request_body, &idempotency_key)
+    );
+
+    assert_eq!(first_response.status(), second_response.status());
+    assert_eq!(
+        first_response.text().await.unwrap(),
+        second_response.text().await.unwrap()
+    );
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[actix_rt::test]
+async fn expired_idempotency_keys_are_processed_again() {
+    let app = spawn_app().await;
+
+    let newsletter_request_body = serde_json::json!({
+        "list_id": app.list_id,
+        "title": "newsletter title",
+        "content": {
+            "text": "plain text body",
+            "html": "<b>html body</b>"
+        }
+    });
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    let first_response = app
+        .post_newsletter_with_idempotency_key(newsletter_request_body.clone(), &idempotency_key)
+        .await;
+    let first_body: serde_json::Value = first_response.json().await.unwrap();
+
+    sqlx::query!("UPDATE idempotency SET created_at = created_at - interval '30 days'")
+        .execute(&app.db_pool)
+        .await
+        .expect("failed to age idempotency keys");
+
+    let second_response = app
+        .post_newsletter_with_idempotency_key(newsletter_request_body, &idempotency_key)
+        .await;
+    assert_eq!(second_response.status(), StatusCode::ACCEPTED);
+    let second_body: serde_json::Value = second_response.json().await.unwrap();
+
+    assert_ne!(
+        first_body["newsletter_issue_id"],
+        second_body["newsletter_issue_id"]
+    );
+}
+
+#[actix_rt::test]
+async fn invalid_idempotency_keys_are_rejected() {
+    let app = spawn_app().await;
+
+    let newsletter_request_body = serde_json::json!({
+        "list_id": app.list_id,
+        "title": "newsletter title",
+        "content": {
+            "text": "plain text body",
+            "html": "<b>html body</b>"
+        }
+    });
+
+    let test_cases = [("".to_string(), "empty"), ("a".repeat(50), "too long")];
+
+    for (idempotency_key, description) in test_cases {
+        let response = app
+            .post_newsletter_with_idempotency_key(newsletter_request_body.clone(), &idempotency_key)
+            .await;
+
+        assert_eq!(
+            response.status(),
+            StatusCode::BAD_REQUEST,
+            "the api didn't 400 for an idempotency key that was {}",
+            description
+        );
+    }
 }
 
 #[actix_rt::test]
@@ -71,10 +510,23 @@ async fn newsletters_returns_400_for_invalid_data() {
         ),
         (
             serde_json::json!({
+                "list_id": app.list_id,
                 "title": "newsletter"
             }),
             "missing contents",
         ),
+        (
+            serde_json::json!({
+                "list_id": app.list_id,
+                "title": "newsletter",
+                "content": {
+                    "text": "text cont",
+                    "html": "<b>html cont</b>"
+                },
+                "markdown": "**markdown cont**"
+            }),
+            "both contents and markdown",
+        ),
     ];
 
     for (body, error_message) in test_cases {
@@ -90,12 +542,63 @@ async fn newsletters_returns_400_for_invalid_data() {
 }
 
 #[actix_rt::test]
+async fn newsletters_with_invalid_templates_are_rejected() {
+    let app = spawn_app().await;
+    let test_cases = [
+        (
+            serde_json::json!({
+                "list_id": app.list_id,
+                "title": "newsletter for {{ nmae }}",
+                "content": {
+                    "text": "text cont",
+                    "html": "<b>html cont</b>"
+                }
+            }),
+            "title",
+            "an unknown merge field",
+        ),
+        (
+            serde_json::json!({
+                "list_id": app.list_id,
+                "title": "newsletter",
+                "content": {
+                    "text": "hi {{ name",
+                    "html": "<b>html cont</b>"
+                }
+            }),
+            "text",
+            "an unclosed merge field",
+        ),
+    ];
+
+    for (body, field, error_message) in test_cases {
+        let response = app.post_newsletter(body).await;
+
+        assert_eq!(
+            response.status(),
+            StatusCode::BAD_REQUEST,
+            "the api didn't 400 for a template with {}",
+            error_message
+        );
+        let text = response.text().await.unwrap();
+        assert!(
+            text.starts_with(&format!("invalid template in {}: ", field)),
+            "unexpected error for a template with {}: {}",
+            error_message,
+            text
+        );
+    }
+}
+
+#[actix_rt::test]
+#[allow(clippy::needless_borrows_for_generic_args)]
 async fn request_missing_auth_are_rejected() {
     let app = spawn_app().await;
 
     let response = reqwest::Client::new()
         .post(&format!("{}/newsletter", &app.address))
         .json(&serde_json::json!({
+            "list_id": app.list_id,
             "title": "some title",
             "content": {
                 "text": "text cont",
@@ -114,6 +617,7 @@ async fn request_missing_auth_are_rejected() {
 }
 
 #[actix_rt::test]
+#[allow(clippy::needless_borrows_for_generic_args)]
 async fn non_existing_user_is_rejected() {
     let app = spawn_app().await;
 
@@ -124,6 +628,7 @@ async fn non_existing_user_is_rejected() {
         .post(&format!("{}/newsletter", &app.address))
         .basic_auth(username, Some(password))
         .json(&serde_json::json!({
+            "list_id": app.list_id,
             "title": "newsletter title",
             "content": {
                 "text": "plain body",
@@ -142,6 +647,7 @@ async fn non_existing_user_is_rejected() {
 }
 
 #[actix_rt::test]
+#[allow(clippy::needless_borrows_for_generic_args)]
 async fn invalid_password_is_rejected() {
     let app = spawn_app().await;
 
@@ -152,6 +658,7 @@ async fn invalid_password_is_rejected() {
         .post(&format!("{}/newsletter", &app.address))
         .basic_auth(username, Some(password))
         .json(&serde_json::json!({
+            "list_id": app.list_id,
             "title": "newsletter title",
             "content": {
                 "text": "plain body",
@@ -168,38 +675,3 @@ async fn invalid_password_is_rejected() {
         response.headers()["WWW-Authenticate"]
     );
 }
-
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let body = "name=joseph&email=joseph@google.com";
-
-    let _mock_guard = Mock::given(path("/email"))
-        .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .named("create unconfirmed subscriber")
-        .expect(1)
-        .mount_as_scoped(&app.email_server)
-        .await;
-    app.post_subscriptions(body.into())
-        .await
-        .error_for_status()
-        .unwrap();
-
-    let email_request = &app
-        .email_server
-        .received_requests()
-        .await
-        .unwrap()
-        .pop()
-        .unwrap();
-
-    app.get_confirmation_links(email_request)
-}
-
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_link = create_unconfirmed_subscriber(app).await;
-    reqwest::get(confirmation_link.html)
-        .await
-        .unwrap()
-        .error_for_status()
-        .unwrap();
-}
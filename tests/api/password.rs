@@ -0,0 +1,278 @@
+use reqwest::{StatusCode, Url};
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::common::{assert_is_redirect_to, spawn_app, TestApp};
+
+fn new_password() -> String {
+    Uuid::new_v4().to_string()
+}
+
+/// Requests a reset link for the test user and returns the token it carries.
+async fn request_reset_token(app: &TestApp) -> String {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let response = app.post_forgot_password(&app.test_user.email).await;
+    assert_is_redirect_to(&response, "/login");
+    app.dispatch_outbox_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], app.test_user.email.as_str());
+    let link = linkify::LinkFinder::new()
+        .links(body["TextBody"].as_str().unwrap())
+        .next()
+        .unwrap()
+        .as_str()
+        .to_owned();
+    let link = Url::parse(&link).unwrap();
+    link.query_pairs()
+        .find(|(key, _)| key == "token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+#[actix_rt::test]
+async fn changing_the_password_requires_a_login() {
+    let app = spawn_app().await;
+
+    let response = app.post_change_password("a", "b", "b").await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_rt::test]
+async fn the_current_password_must_be_correct() {
+    let app = spawn_app().await;
+    app.log_in_as_test_user().await;
+    let password = new_password();
+
+    let response = app
+        .post_change_password("wrong-password", &password, &password)
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let html = app
+        .get_session_admin("/admin/password")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("<i>The current password is incorrect.</i>"));
+}
+
+#[actix_rt::test]
+async fn new_passwords_must_follow_the_policy() {
+    let app = spawn_app().await;
+    app.log_in_as_test_user().await;
+    let current = app.test_user.password.clone();
+    let test_cases = vec![
+        (
+            new_password(),
+            new_password(),
+            "You entered two different new passwords.",
+        ),
+        (
+            "short".to_string(),
+            "short".to_string(),
+            "at least 12 characters",
+        ),
+        ("a".repeat(129), "a".repeat(129), "at most 128 characters"),
+    ];
+
+    for (password, check, message) in test_cases {
+        let response = app.post_change_password(&current, &password, &check).await;
+        assert_is_redirect_to(&response, "/admin/password");
+
+        let html = app
+            .get_session_admin("/admin/password")
+            .await
+            .text()
+            .await
+            .unwrap();
+        assert!(html.contains(message), "missing message: {}", message);
+    }
+}
+
+#[actix_rt::test]
+async fn changed_passwords_are_used_to_log_in() {
+    let app = spawn_app().await;
+    app.log_in_as_test_user().await;
+    let password = new_password();
+
+    let response = app
+        .post_change_password(&app.test_user.password, &password, &password)
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+    let html = app
+        .get_session_admin("/admin/password")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("<i>Your password has been changed.</i>"));
+
+    let stored = sqlx::query!(
+        "SELECT password_hash FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(stored.password_hash.starts_with("$argon2id$"));
+    app.post_logout().await;
+    let response = app
+        .post_login(&app.test_user.username, &app.test_user.password)
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app.post_login(&app.test_user.username, &password).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[actix_rt::test]
+async fn changing_the_password_logs_out_other_sessions() {
+    let app = spawn_app().await;
+    app.log_in_as_test_user().await;
+    let other_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    let response = other_client
+        .post(format!("{}/login", app.address))
+        .form(&[
+            ("username", app.test_user.username.as_str()),
+            ("password", app.test_user.password.as_str()),
+        ])
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let password = new_password();
+
+    let response = app
+        .post_change_password(&app.test_user.password, &password, &password)
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/password");
+    let response = app.get_session_admin("/admin/dashboard").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = other_client
+        .get(format!("{}/admin/dashboard", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[actix_rt::test]
+async fn unknown_addresses_get_the_same_answer_and_no_email() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_forgot_password("nobody@example.com").await;
+    assert_is_redirect_to(&response, "/login");
+    app.dispatch_outbox_emails().await;
+
+    let html = app.get_login_form().await;
+    assert!(html.contains("a reset link is on its way"));
+}
+
+#[actix_rt::test]
+async fn a_failing_email_provider_does_not_change_the_answer() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_forgot_password(&app.test_user.email).await;
+
+    assert_is_redirect_to(&response, "/login");
+    let html = app.get_login_form().await;
+    assert!(html.contains("a reset link is on its way"));
+}
+
+#[actix_rt::test]
+async fn reset_links_set_a_new_password_once() {
+    let app = spawn_app().await;
+    app.log_in_as_test_user().await;
+    let token = request_reset_token(&app).await;
+    let password = new_password();
+
+    let response = app
+        .api_client
+        .get(format!("{}/password/reset?token={}", app.address, token))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app.post_reset_password(&token, &password, &password).await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app.get_session_admin("/admin/dashboard").await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app.post_login(&app.test_user.username, &password).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let other_password = new_password();
+    let response = app
+        .post_reset_password(&token, &other_password, &other_password)
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn expired_reset_links_are_rejected() {
+    let app = spawn_app().await;
+    let token = request_reset_token(&app).await;
+    sqlx::query!("UPDATE password_reset_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let password = new_password();
+
+    let response = app.post_reset_password(&token, &password, &password).await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = app
+        .post_login(&app.test_user.username, &app.test_user.password)
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[actix_rt::test]
+async fn reset_passwords_must_follow_the_policy() {
+    let app = spawn_app().await;
+    let token = request_reset_token(&app).await;
+
+    let response = app.post_reset_password(&token, "short", "short").await;
+
+    assert_is_redirect_to(&response, &format!("/password/reset?token={}", token));
+    let html = app
+        .api_client
+        .get(format!("{}/password/reset?token={}", app.address, token))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("at least 12 characters"));
+}
@@ -0,0 +1,178 @@
+use reqwest::StatusCode;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::common::{create_confirmed_subscriber, spawn_app, TestApp, TestUser};
+
+async fn user_with_roles(app: &TestApp, roles: &[&str]) -> TestUser {
+    let user = TestUser::generate();
+    user.store(&app.db_pool, roles).await;
+    user
+}
+
+fn newsletter_body(app: &TestApp) -> serde_json::Value {
+    serde_json::json!({
+        "list_id": app.list_id,
+        "title": "newsletter title",
+        "content": {
+            "text": "plain text body",
+            "html": "<b>html body</b>"
+        }
+    })
+}
+
+async fn publish_as(app: &TestApp, user: &TestUser) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/newsletter", app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .json(&newsletter_body(app))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn get_as(app: &TestApp, user: &TestUser, path: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}{}", app.address, path))
+        .basic_auth(&user.username, Some(&user.password))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn delete_as(app: &TestApp, user: &TestUser, path: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .delete(format!("{}{}", app.address, path))
+        .basic_auth(&user.username, Some(&user.password))
+        .send()
+        .await
+        .unwrap()
+}
+
+#[actix_rt::test]
+async fn viewers_can_read_statistics_but_not_subscribers() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    let viewer = user_with_roles(&app, &["viewer"]).await;
+
+    let response = get_as(&app, &viewer, "/admin/subscribers/stats").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let stats: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(stats["by_status"]["confirmed"], 1);
+    let list = stats["lists"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|list| list["list_id"] == app.list_id.to_string())
+        .unwrap();
+    assert_eq!(list["by_status"]["confirmed"], 1);
+
+    for path in [
+        "/admin/subscribers".to_string(),
+        format!("/admin/subscribers/{}", subscriber_id),
+    ] {
+        let response = get_as(&app, &viewer, &path).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            response.text().await.unwrap(),
+            "You are not allowed to read subscribers"
+        );
+    }
+}
+
+#[actix_rt::test]
+async fn viewers_cannot_publish() {
+    let app = spawn_app().await;
+    let viewer = user_with_roles(&app, &["viewer"]).await;
+
+    let response = publish_as(&app, &viewer).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(
+        response.text().await.unwrap(),
+        "You are not allowed to publish newsletters"
+    );
+}
+
+#[actix_rt::test]
+async fn editors_can_publish_but_not_manage_subscribers() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let editor = user_with_roles(&app, &["editor"]).await;
+
+    let response = publish_as(&app, &editor).await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    let response = get_as(&app, &editor, "/admin/subscribers").await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = delete_as(
+        &app,
+        &editor,
+        &format!("/admin/subscribers/{}", Uuid::new_v4()),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[actix_rt::test]
+async fn users_without_a_role_are_forbidden() {
+    let app = spawn_app().await;
+    let user = user_with_roles(&app, &[]).await;
+
+    let response = get_as(&app, &user, "/admin/subscribers").await;
+
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[actix_rt::test]
+async fn wrong_credentials_are_still_unauthorized() {
+    let app = spawn_app().await;
+    let mut viewer = user_with_roles(&app, &["viewer"]).await;
+    viewer.password = Uuid::new_v4().to_string();
+
+    let response = publish_as(&app, &viewer).await;
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        response.headers()["WWW-Authenticate"],
+        r#"Basic realm="publish""#
+    );
+}
+
+#[actix_rt::test]
+async fn api_tokens_cannot_exceed_their_owners_role() {
+    let app = spawn_app().await;
+    let viewer = user_with_roles(&app, &["viewer"]).await;
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/tokens", app.address))
+        .basic_auth(&viewer.username, Some(&viewer.password))
+        .json(&serde_json::json!({ "name": "ci", "scopes": ["newsletter:publish"] }))
+        .send()
+        .await
+        .unwrap();
+    let body: serde_json::Value = response.json().await.unwrap();
+    let token = body["token"].as_str().unwrap();
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletter", app.address))
+        .bearer_auth(token)
+        .json(&newsletter_body(&app))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
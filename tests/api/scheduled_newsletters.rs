@@ -0,0 +1,215 @@
+use actix_http::StatusCode;
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::common::{create_confirmed_subscriber, spawn_app, TestApp};
+
+fn scheduled_newsletter_body(app: &TestApp, send_at: chrono::DateTime<Utc>) -> serde_json::Value {
+    serde_json::json!({
+        "list_id": app.list_id,
+        "title": "newsletter title",
+        "content": {
+            "text": "plain text body",
+            "html": "<b>html body</b>"
+        },
+        "send_at": send_at
+    })
+}
+
+async fn schedule_newsletter(app: &TestApp) -> String {
+    let response = app
+        .post_newsletter(scheduled_newsletter_body(
+            app,
+            Utc::now() + Duration::days(3),
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["newsletter_issue_id"].as_str().unwrap().to_owned()
+}
+
+async fn queued_delivery_tasks(app: &TestApp) -> usize {
+    sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("failed to query the delivery queue")
+        .len()
+}
+
+#[actix_rt::test]
+async fn scheduled_newsletters_are_not_queued_before_they_are_due() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let issue_id = schedule_newsletter(&app).await;
+    app.release_due_issues().await;
+
+    assert_eq!(queued_delivery_tasks(&app).await, 0);
+
+    let scheduled: serde_json::Value = app.get_scheduled_newsletters().await.json().await.unwrap();
+    assert_eq!(scheduled.as_array().unwrap().len(), 1);
+    assert_eq!(scheduled[0]["newsletter_issue_id"], issue_id.as_str());
+}
+
+#[actix_rt::test]
+async fn scheduled_newsletters_are_delivered_once_they_are_due() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = schedule_newsletter(&app).await;
+    let response = app.put_scheduled_newsletter(&issue_id, Utc::now()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    app.release_due_issues().await;
+    assert_eq!(queued_delivery_tasks(&app).await, 1);
+    app.dispatch_all_pending_emails().await;
+
+    let scheduled: serde_json::Value = app.get_scheduled_newsletters().await.json().await.unwrap();
+    assert!(scheduled.as_array().unwrap().is_empty());
+}
+
+#[actix_rt::test]
+async fn rescheduling_a_newsletter_changes_its_send_time() {
+    let app = spawn_app().await;
+
+    let issue_id = schedule_newsletter(&app).await;
+    let send_at = Utc::now() + Duration::days(7);
+    let response = app.put_scheduled_newsletter(&issue_id, send_at).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let saved = sqlx::query!(r#"SELECT scheduled_for as "scheduled_for!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("failed to query newsletter issues");
+    assert_eq!(saved.scheduled_for.timestamp(), send_at.timestamp());
+}
+
+#[actix_rt::test]
+async fn newsletters_cannot_be_scheduled_in_the_past() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_newsletter(scheduled_newsletter_body(
+            &app,
+            Utc::now() - Duration::hours(1),
+        ))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(response.text().await.unwrap().ends_with("is in the past"));
+    let issues = sqlx::query!(r#"SELECT count(*) as "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("failed to query newsletter issues")
+        .count;
+    assert_eq!(issues, 0);
+}
+
+#[actix_rt::test]
+async fn a_send_time_just_behind_our_clock_is_sent_right_away() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_newsletter(scheduled_newsletter_body(
+            &app,
+            Utc::now() - Duration::seconds(10),
+        ))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let issue = sqlx::query!("SELECT status FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("failed to query newsletter issues");
+    assert_eq!(issue.status, "publishing");
+}
+
+#[actix_rt::test]
+async fn newsletters_cannot_be_rescheduled_into_the_past() {
+    let app = spawn_app().await;
+    let issue_id = schedule_newsletter(&app).await;
+    let before = sqlx::query!(r#"SELECT scheduled_for as "scheduled_for!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("failed to query newsletter issues")
+        .scheduled_for;
+
+    let response = app
+        .put_scheduled_newsletter(&issue_id, Utc::now() - Duration::hours(1))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(response.text().await.unwrap().ends_with("is in the past"));
+    let after = sqlx::query!(r#"SELECT scheduled_for as "scheduled_for!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("failed to query newsletter issues")
+        .scheduled_for;
+    assert_eq!(after, before);
+}
+
+#[actix_rt::test]
+async fn cancelled_newsletters_are_never_delivered() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let issue_id = schedule_newsletter(&app).await;
+    let response = app.delete_scheduled_newsletter(&issue_id).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    sqlx::query!("UPDATE newsletter_issues SET scheduled_for = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .expect("failed to move the schedule into the past");
+    app.release_due_issues().await;
+
+    assert_eq!(queued_delivery_tasks(&app).await, 0);
+    let scheduled: serde_json::Value = app.get_scheduled_newsletters().await.json().await.unwrap();
+    assert!(scheduled.as_array().unwrap().is_empty());
+}
+
+#[actix_rt::test]
+async fn unknown_scheduled_newsletters_return_404() {
+    let app = spawn_app().await;
+    let issue_id = Uuid::new_v4().to_string();
+
+    let response = app.delete_scheduled_newsletter(&issue_id).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        response.text().await.unwrap(),
+        format!("no scheduled issue with id {}", issue_id)
+    );
+
+    let response = app
+        .put_scheduled_newsletter(&issue_id, Utc::now() + Duration::days(1))
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        response.text().await.unwrap(),
+        format!("no scheduled issue with id {}", issue_id)
+    );
+}
+
+#[actix_rt::test]
+async fn listing_scheduled_newsletters_requires_authentication() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/newsletter/scheduled", &app.address))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,103 @@
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use uuid::Uuid;
+use zero2prod::subscription_cleanup::purge_stale_subscriptions;
+
+use crate::common::{create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app};
+
+const RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[actix_rt::test]
+async fn expired_tokens_and_stale_pending_subscribers_are_purged() {
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '8 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!("UPDATE subscriptions SET subscribed_at = now() - interval '9 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let outcome = purge_stale_subscriptions(&app.db_pool, RETENTION)
+        .await
+        .unwrap();
+
+    assert_eq!(outcome.expired_tokens, 1);
+    assert_eq!(outcome.pending_subscribers, 1);
+    let remaining = sqlx::query!(r#"SELECT count(*) as "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(remaining, 0);
+}
+
+#[actix_rt::test]
+async fn recent_and_confirmed_subscribers_are_kept() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscriptions SET subscribed_at = now() - interval '8 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, 'recent@example.com', 'recent', now(), 'pending')
+        "#,
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let outcome = purge_stale_subscriptions(&app.db_pool, RETENTION)
+        .await
+        .unwrap();
+
+    assert_eq!(outcome.pending_subscribers, 0);
+    let remaining = sqlx::query!(r#"SELECT count(*) as "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(remaining, 2);
+}
+
+#[actix_rt::test]
+async fn pending_subscribers_with_a_live_token_are_kept() {
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscriptions SET subscribed_at = now() - interval '8 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let outcome = purge_stale_subscriptions(&app.db_pool, RETENTION)
+        .await
+        .unwrap();
+
+    assert_eq!(outcome.expired_tokens, 0);
+    assert_eq!(outcome.pending_subscribers, 0);
+}
+
+#[actix_rt::test]
+async fn recently_expired_links_still_render_the_expired_page_after_a_purge() {
+    let app = spawn_app().await;
+    let links = create_unconfirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let outcome = purge_stale_subscriptions(&app.db_pool, RETENTION)
+        .await
+        .unwrap();
+
+    assert_eq!(outcome.expired_tokens, 0);
+    let response = reqwest::get(links.html).await.unwrap();
+    assert_eq!(response.status(), StatusCode::GONE);
+}
@@ -4,12 +4,20 @@ use wiremock::{
     Mock, ResponseTemplate,
 };
 
-use crate::common::spawn_app;
+use uuid::Uuid;
+use zero2prod::subscription_token::{hash_outstanding_tokens, hash_subscription_token};
+
+use crate::common::{
+    create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app, TestApp,
+};
 
 #[actix_rt::test]
 async fn subscribe_returns_200_for_valid_form_and_sends_email() {
     let test_app = spawn_app().await;
-    let body = "name=joseph&email=jchevertonwynne%40gmail.com";
+    let body = format!(
+        "name=joseph&email=jchevertonwynne%40gmail.com&list_id={}",
+        test_app.list_id
+    );
 
     Mock::given(path("/email"))
         .and(method("POST"))
@@ -18,7 +26,8 @@ async fn subscribe_returns_200_for_valid_form_and_sends_email() {
         .mount(&test_app.email_server)
         .await;
 
-    let response = test_app.post_subscriptions(body.to_string()).await;
+    let response = test_app.post_subscriptions(body).await;
+    test_app.dispatch_outbox_emails().await;
 
     assert_eq!(response.status(), StatusCode::OK);
     let saved = sqlx::query!("select email, name, status from subscriptions")
@@ -40,14 +49,18 @@ async fn subscribe_returns_200_for_valid_form_and_sends_email() {
 async fn subscribe_returns_400_for_invalid_form() {
     let test_app = spawn_app().await;
 
+    let list_id = test_app.list_id;
     let test_cases = [
-        ("name=joseph", "missing email"),
-        ("email=jchevertonwynne%40gmail.com", "missing name"),
-        ("", "missing both params"),
+        (format!("name=joseph&list_id={}", list_id), "missing email"),
+        (
+            format!("email=jchevertonwynne%40gmail.com&list_id={}", list_id),
+            "missing name",
+        ),
+        ("".to_string(), "missing all params"),
     ];
 
     for (body, description) in test_cases {
-        let response = test_app.post_subscriptions(body.to_string()).await;
+        let response = test_app.post_subscriptions(body).await;
 
         assert_eq!(
             response.status().as_u16(),
@@ -69,7 +82,8 @@ async fn subscribe_returns_400_when_fields_are_present_but_empty() {
     ];
 
     for (body, description) in test_cases {
-        let response = test_app.post_subscriptions(body.to_string()).await;
+        let body = format!("{}&list_id={}", body, test_app.list_id);
+        let response = test_app.post_subscriptions(body).await;
 
         assert_eq!(
             response.status().as_u16(),
@@ -117,9 +131,13 @@ async fn confirmation_requests_with_valid_token_are_accepted_and_user_marked_as_
         .await;
 
     let response = test_app
-        .post_subscriptions("name=joseph&email=jchevertonwynne1%40gmail.com".to_string())
+        .post_subscriptions(format!(
+            "name=joseph&email=jchevertonwynne1%40gmail.com&list_id={}",
+            test_app.list_id
+        ))
         .await;
     assert_eq!(response.status(), StatusCode::OK);
+    test_app.dispatch_outbox_emails().await;
 
     let confirmation_status = sqlx::query!(
         "SELECT status FROM subscriptions WHERE email = $1",
@@ -136,6 +154,20 @@ async fn confirmation_requests_with_valid_token_are_accepted_and_user_marked_as_
 
     let response = reqwest::get(links.html.as_ref()).await.unwrap();
     assert_eq!(response.status(), StatusCode::OK);
+    let page = response.text().await.unwrap();
+    assert!(page.contains(r#"method="post""#));
+
+    let confirmation_status = sqlx::query!(
+        "SELECT status FROM subscriptions WHERE email = $1",
+        "jchevertonwynne1@gmail.com"
+    )
+    .fetch_one(&test_app.db_pool)
+    .await
+    .expect("query failed");
+    assert_eq!(confirmation_status.status, "pending");
+
+    let response = test_app.confirm_subscription(&links.html).await;
+    assert_eq!(response.status(), StatusCode::OK);
 
     let confirmation_status = sqlx::query!(
         "SELECT status FROM subscriptions WHERE email = $1",
@@ -150,14 +182,563 @@ async fn confirmation_requests_with_valid_token_are_accepted_and_user_marked_as_
 #[actix_rt::test]
 async fn subscribe_fails_on_fatal_db_error() {
     let test_app = spawn_app().await;
-    let valid_input = "name=joseph&email=jchevertonwynne%40gmail.com";
+    let valid_input = format!(
+        "name=joseph&email=jchevertonwynne%40gmail.com&list_id={}",
+        test_app.list_id
+    );
 
     sqlx::query!("ALTER TABLE subscriptions DROP COLUMN email;")
         .execute(&test_app.db_pool)
         .await
         .unwrap();
 
-    let response = test_app.post_subscriptions(valid_input.to_string()).await;
+    let response = test_app.post_subscriptions(valid_input).await;
 
     assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
 }
+
+#[actix_rt::test]
+async fn subscribe_returns_400_for_an_unknown_list() {
+    let test_app = spawn_app().await;
+
+    let response = test_app
+        .post_subscriptions(format!(
+            "name=joseph&email=jchevertonwynne%40gmail.com&list_id={}",
+            Uuid::new_v4()
+        ))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["field"], "list_id");
+}
+
+#[actix_rt::test]
+async fn subscribing_without_a_list_joins_the_newsletter_list() {
+    let test_app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = test_app
+        .post_subscriptions("name=joseph&email=jchevertonwynne%40gmail.com".to_string())
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let list = sqlx::query!(
+        r#"
+        SELECT lists.name AS "name!" FROM list_subscriptions
+        JOIN lists ON lists.list_id = list_subscriptions.list_id
+        "#
+    )
+    .fetch_one(&test_app.db_pool)
+    .await
+    .expect("failed to fetch list subscription");
+    assert_eq!(list.name, "newsletter");
+}
+
+#[actix_rt::test]
+async fn subscribe_accepts_json_bodies() {
+    let test_app = spawn_app().await;
+
+    let response = test_app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "joseph",
+            "email": "jchevertonwynne@gmail.com",
+            "list_id": test_app.list_id
+        }))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(list_subscription_status(&test_app).await, "pending");
+}
+
+#[actix_rt::test]
+async fn validation_errors_name_the_offending_field() {
+    let test_app = spawn_app().await;
+    let test_cases = [
+        ("", "jchevertonwynne@gmail.com", "name"),
+        ("joseph", "yolo", "email"),
+    ];
+
+    for (name, email, field) in test_cases {
+        let json_response = test_app
+            .post_subscriptions_json(&serde_json::json!({
+                "name": name,
+                "email": email,
+                "list_id": test_app.list_id
+            }))
+            .await;
+        let form_response = test_app
+            .post_subscriptions(format!(
+                "name={}&email={}&list_id={}",
+                name, email, test_app.list_id
+            ))
+            .await;
+
+        for response in [json_response, form_response] {
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            let body: serde_json::Value = response.json().await.unwrap();
+            assert_eq!(body["field"], field);
+            assert!(body["message"].is_string());
+        }
+    }
+}
+
+#[actix_rt::test]
+async fn malformed_json_bodies_are_rejected() {
+    let test_app = spawn_app().await;
+
+    let response = test_app
+        .post_subscriptions_json(&serde_json::json!({ "name": "joseph" }))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn confirmation_is_tracked_separately_for_each_list() {
+    let test_app = spawn_app().await;
+    let other_list_id = test_app.create_list("other list").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+
+    for list_id in [test_app.list_id, other_list_id] {
+        let response = test_app
+            .post_subscriptions(format!(
+                "name=joseph&email=jchevertonwynne%40gmail.com&list_id={}",
+                list_id
+            ))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    test_app.dispatch_outbox_emails().await;
+
+    let requests = test_app.email_server.received_requests().await.unwrap();
+    let links = test_app.get_confirmation_links(&requests[0]);
+    test_app
+        .confirm_subscription(&links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let subscriber_count = sqlx::query!(r#"SELECT count(*) as "count!" FROM subscriptions"#)
+        .fetch_one(&test_app.db_pool)
+        .await
+        .expect("query failed")
+        .count;
+    assert_eq!(subscriber_count, 1);
+
+    let statuses = sqlx::query!("SELECT list_id, status FROM list_subscriptions ORDER BY status",)
+        .fetch_all(&test_app.db_pool)
+        .await
+        .expect("query failed");
+    assert_eq!(statuses.len(), 2);
+    assert_eq!(statuses[0].list_id, test_app.list_id);
+    assert_eq!(statuses[0].status, "confirmed");
+    assert_eq!(statuses[1].list_id, other_list_id);
+    assert_eq!(statuses[1].status, "pending");
+}
+
+async fn list_subscription_status(test_app: &TestApp) -> String {
+    sqlx::query!(
+        "SELECT status FROM list_subscriptions WHERE list_id = $1",
+        test_app.list_id
+    )
+    .fetch_one(&test_app.db_pool)
+    .await
+    .expect("query failed")
+    .status
+}
+
+async fn subscriber_count(test_app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT count(*) as "count!" FROM subscriptions"#)
+        .fetch_one(&test_app.db_pool)
+        .await
+        .expect("query failed")
+        .count
+}
+
+#[actix_rt::test]
+async fn subscribing_twice_while_pending_resends_the_confirmation_email() {
+    let test_app = spawn_app().await;
+
+    // each call expects exactly one confirmation email to be sent
+    create_unconfirmed_subscriber(&test_app).await;
+    let second_links = create_unconfirmed_subscriber(&test_app).await;
+
+    assert_eq!(subscriber_count(&test_app).await, 1);
+    assert_eq!(list_subscription_status(&test_app).await, "pending");
+
+    test_app
+        .confirm_subscription(&second_links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+    assert_eq!(list_subscription_status(&test_app).await, "confirmed");
+}
+
+#[actix_rt::test]
+async fn subscribing_again_once_confirmed_is_a_no_op() {
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = test_app
+        .post_subscriptions(format!(
+            "name=joseph&email=joseph%40google.com&list_id={}",
+            test_app.list_id
+        ))
+        .await;
+    test_app.dispatch_outbox_emails().await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(subscriber_count(&test_app).await, 1);
+    assert_eq!(list_subscription_status(&test_app).await, "confirmed");
+}
+
+#[actix_rt::test]
+async fn unsubscribed_addresses_have_to_confirm_again() {
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    sqlx::query!("UPDATE list_subscriptions SET status = 'unsubscribed'")
+        .execute(&test_app.db_pool)
+        .await
+        .expect("query failed");
+
+    let links = create_unconfirmed_subscriber(&test_app).await;
+    assert_eq!(list_subscription_status(&test_app).await, "pending");
+
+    test_app
+        .confirm_subscription(&links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+    assert_eq!(list_subscription_status(&test_app).await, "confirmed");
+}
+
+#[actix_rt::test]
+async fn expired_confirmation_links_are_rejected_with_an_error_page() {
+    let test_app = spawn_app().await;
+    let links = create_unconfirmed_subscriber(&test_app).await;
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&test_app.db_pool)
+        .await
+        .expect("query failed");
+
+    let response = reqwest::get(links.html).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::GONE);
+    assert!(response.text().await.unwrap().contains("expired"));
+    assert_eq!(list_subscription_status(&test_app).await, "pending");
+}
+
+#[actix_rt::test]
+async fn posting_an_expired_token_does_not_confirm_the_subscription() {
+    let test_app = spawn_app().await;
+    let links = create_unconfirmed_subscriber(&test_app).await;
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&test_app.db_pool)
+        .await
+        .expect("query failed");
+    let (_, token) = links
+        .html
+        .query_pairs()
+        .find(|(key, _)| key == "subscription_token")
+        .unwrap();
+
+    let response = test_app.post_confirmation(&token).await;
+
+    assert_eq!(response.status(), StatusCode::GONE);
+    assert_eq!(list_subscription_status(&test_app).await, "pending");
+}
+
+#[actix_rt::test]
+async fn posting_an_unknown_token_is_rejected() {
+    let test_app = spawn_app().await;
+
+    let response = test_app.post_confirmation("hello").await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn opening_the_confirmation_page_repeatedly_does_not_consume_the_token() {
+    let test_app = spawn_app().await;
+    let links = create_unconfirmed_subscriber(&test_app).await;
+
+    for _ in 0..2 {
+        reqwest::get(links.html.clone())
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+    assert_eq!(list_subscription_status(&test_app).await, "pending");
+
+    test_app
+        .confirm_subscription(&links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+    assert_eq!(list_subscription_status(&test_app).await, "confirmed");
+}
+
+#[actix_rt::test]
+async fn subscription_tokens_are_not_stored_in_plaintext() {
+    let test_app = spawn_app().await;
+    let links = create_unconfirmed_subscriber(&test_app).await;
+    let token = links
+        .html
+        .query_pairs()
+        .find(|(key, _)| key == "subscription_token")
+        .unwrap()
+        .1
+        .into_owned();
+
+    let stored =
+        sqlx::query!("SELECT subscription_token, subscription_token_hash FROM subscription_tokens")
+            .fetch_one(&test_app.db_pool)
+            .await
+            .expect("query failed");
+
+    assert_eq!(stored.subscription_token, None);
+    let stored_hash = stored.subscription_token_hash.unwrap();
+    assert!(!stored_hash.contains(&token));
+    assert_eq!(
+        stored_hash,
+        hash_subscription_token(&token, &test_app.hmac_secret)
+    );
+}
+
+#[actix_rt::test]
+async fn outstanding_plaintext_tokens_keep_working_once_hashed() {
+    let test_app = spawn_app().await;
+    create_unconfirmed_subscriber(&test_app).await;
+    sqlx::query!(
+        r#"
+        UPDATE subscription_tokens
+        SET subscription_token = 'legacytoken', subscription_token_hash = NULL
+        "#
+    )
+    .execute(&test_app.db_pool)
+    .await
+    .expect("query failed");
+
+    let hashed = hash_outstanding_tokens(&test_app.db_pool, &test_app.hmac_secret)
+        .await
+        .unwrap();
+    assert_eq!(hashed, 1);
+
+    let response = test_app.post_confirmation("legacytoken").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(list_subscription_status(&test_app).await, "confirmed");
+}
+
+#[actix_rt::test]
+async fn confirmation_emails_are_sent_after_the_subscription_is_committed() {
+    let test_app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = test_app
+        .post_subscriptions(format!(
+            "name=joseph&email=jchevertonwynne%40gmail.com&list_id={}",
+            test_app.list_id
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty());
+
+    test_app.dispatch_outbox_emails().await;
+    let outbox_size = sqlx::query!(r#"SELECT count(*) as "count!" FROM email_outbox"#)
+        .fetch_one(&test_app.db_pool)
+        .await
+        .expect("query failed")
+        .count;
+    assert_eq!(outbox_size, 0);
+}
+
+#[actix_rt::test]
+async fn subscribing_succeeds_while_the_email_provider_is_down_and_the_email_is_retried() {
+    let test_app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = test_app
+        .post_subscriptions(format!(
+            "name=joseph&email=jchevertonwynne%40gmail.com&list_id={}",
+            test_app.list_id
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    test_app.dispatch_outbox_emails().await;
+    let pending = sqlx::query!("SELECT status, n_attempts FROM email_outbox")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .expect("query failed");
+    assert_eq!(pending.status, "pending");
+    assert_eq!(pending.n_attempts, 1);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+    sqlx::query!("UPDATE email_outbox SET execute_after = now()")
+        .execute(&test_app.db_pool)
+        .await
+        .expect("query failed");
+
+    test_app.dispatch_outbox_emails().await;
+    let requests = test_app.email_server.received_requests().await.unwrap();
+    let links = test_app.get_confirmation_links(&requests[1]);
+    test_app
+        .confirm_subscription(&links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+    assert_eq!(list_subscription_status(&test_app).await, "confirmed");
+}
+
+#[actix_rt::test]
+async fn emails_that_cannot_be_sent_are_kept_without_their_bodies() {
+    let test_app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(400))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = test_app
+        .post_subscriptions(format!(
+            "name=joseph&email=jchevertonwynne%40gmail.com&list_id={}",
+            test_app.list_id
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    test_app.dispatch_outbox_emails().await;
+    let failed = sqlx::query!("SELECT status, html_body, text_body, last_error FROM email_outbox")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .expect("query failed");
+    assert_eq!(failed.status, "failed");
+    assert!(failed.last_error.is_some());
+    assert_eq!(failed.html_body, "");
+    assert_eq!(failed.text_body, "");
+}
+
+#[actix_rt::test]
+async fn confirming_twice_renders_the_already_confirmed_page() {
+    let test_app = spawn_app().await;
+    let links = create_unconfirmed_subscriber(&test_app).await;
+    let response = test_app.confirm_subscription(&links.html).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let page = response.text().await.unwrap();
+    assert!(page.contains("Subscription confirmed"));
+    assert!(page.contains("Zero To Production"));
+
+    let response = test_app.confirm_subscription(&links.html).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("has already been confirmed"));
+}
+
+#[actix_rt::test]
+async fn unknown_tokens_render_an_error_page() {
+    let test_app = spawn_app().await;
+
+    let response = test_app.post_confirmation("hello").await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/html; charset=utf-8"
+    );
+    assert!(response.text().await.unwrap().contains("not valid"));
+}
+
+#[actix_rt::test]
+async fn json_clients_get_a_machine_readable_outcome() {
+    let test_app = spawn_app().await;
+    let links = create_unconfirmed_subscriber(&test_app).await;
+    let (_, token) = links
+        .html
+        .query_pairs()
+        .find(|(key, _)| key == "subscription_token")
+        .unwrap();
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions/confirm", test_app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Accept", "application/json")
+        .body(format!("subscription_token={}", token))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "confirmed");
+    assert!(body["message"].as_str().unwrap().contains("test list"));
+}
+
+#[actix_rt::test]
+async fn json_clients_are_told_when_a_link_has_expired() {
+    let test_app = spawn_app().await;
+    let links = create_unconfirmed_subscriber(&test_app).await;
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&test_app.db_pool)
+        .await
+        .expect("query failed");
+
+    let response = reqwest::Client::new()
+        .get(links.html)
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::GONE);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "expired_token");
+}
@@ -0,0 +1,185 @@
+use actix_http::StatusCode;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::common::{create_confirmed_subscriber, spawn_app, TestApp};
+
+async fn publish_newsletter(app: &TestApp) {
+    let response = app
+        .post_newsletter(serde_json::json!({
+            "list_id": app.list_id,
+            "title": "newsletter title",
+            "content": {
+                "text": "plain text body",
+                "html": "<b>html body</b>"
+            }
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+}
+
+async fn publish_and_deliver_newsletter(app: &TestApp) {
+    publish_newsletter(app).await;
+    app.dispatch_all_pending_emails().await;
+}
+
+async fn list_subscription_status(app: &TestApp) -> String {
+    sqlx::query!(
+        "SELECT status FROM list_subscriptions WHERE list_id = $1",
+        app.list_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("failed to fetch list subscription")
+    .status
+}
+
+#[actix_rt::test]
+async fn newsletters_carry_one_click_unsubscribe_headers_and_a_footer_link() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_and_deliver_newsletter(&app).await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let headers = body["Headers"].as_array().unwrap();
+    assert!(headers
+        .iter()
+        .any(|header| header["Name"] == "List-Unsubscribe-Post"
+            && header["Value"] == "List-Unsubscribe=One-Click"));
+
+    let unsubscribe_link = app.get_unsubscribe_link(email_request);
+    assert_eq!(unsubscribe_link.path(), "/subscriptions/unsubscribe");
+    let mut footer_link = unsubscribe_link.clone();
+    footer_link.set_port(None).unwrap();
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains(footer_link.as_str()));
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains(footer_link.as_str()));
+}
+
+#[actix_rt::test]
+async fn one_click_unsubscribe_stops_further_newsletters() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_and_deliver_newsletter(&app).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let unsubscribe_link = app.get_unsubscribe_link(email_request);
+    // Already queued for the subscriber when they unsubscribe.
+    publish_newsletter(&app).await;
+    app.release_due_issues().await;
+
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(list_subscription_status(&app).await, "unsubscribed");
+
+    app.dispatch_all_pending_emails().await;
+    publish_and_deliver_newsletter(&app).await;
+    let queued = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(queued, 1);
+}
+
+#[actix_rt::test]
+async fn the_footer_link_asks_for_confirmation_before_unsubscribing() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    publish_and_deliver_newsletter(&app).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let unsubscribe_link = app.get_unsubscribe_link(email_request);
+
+    let response = reqwest::get(unsubscribe_link.clone()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let page = response.text().await.unwrap();
+    assert!(page.contains(&format!(
+        r#"<form action="/subscriptions/unsubscribe?{}" method="post">"#,
+        unsubscribe_link.query().unwrap()
+    )));
+    assert!(page.contains(r#"<input type="hidden" name="List-Unsubscribe" value="One-Click">"#));
+    assert_eq!(list_subscription_status(&app).await, "confirmed");
+
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .form(&[("List-Unsubscribe", "One-Click")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let page = response.text().await.unwrap();
+    assert!(page.contains("Zero To Production"));
+    assert!(page.contains("<p>You have been unsubscribed.</p>"));
+    assert_eq!(list_subscription_status(&app).await, "unsubscribed");
+}
+
+#[actix_rt::test]
+async fn invalid_unsubscribe_tokens_are_rejected() {
+    let app = spawn_app().await;
+
+    for query in ["", "?token=", "?token=not-a-real-token"] {
+        let response = reqwest::get(format!(
+            "{}/subscriptions/unsubscribe{}",
+            app.address, query
+        ))
+        .await
+        .unwrap();
+
+        assert_eq!(
+            response.status(),
+            StatusCode::BAD_REQUEST,
+            "the api did not reject the query {:?}",
+            query
+        );
+        let page = response.text().await.unwrap();
+        assert!(page.contains("Zero To Production"));
+        assert!(page.contains("This unsubscribe link is not valid."));
+
+        let response = reqwest::Client::new()
+            .post(format!(
+                "{}/subscriptions/unsubscribe{}",
+                app.address, query
+            ))
+            .form(&[("List-Unsubscribe", "One-Click")])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}